@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str =
+    "usage: masker [--seed <n>] [--resume | --dry-run | --export <file.sql> | --discover <file.yaml>]
+
+options:
+  --resume         continue the interrupted run from the progress saved in the checkpoint file
+  --seed <n>       generate values from the given seed, so that the same data is always masked
+                   the same way, overrides the seed set in the config
+  --dry-run        show how many rows would be masked and how some of them would look like,
+                   without writing anything to the DB
+  --export <file>  write the statements that mask the data to the SQL script instead of
+                   executing them, the DB is only read
+  --discover <file>
+                   look for columns that likely hold personal data and write the draft config
+                   that masks them, only the db section of the config is needed";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    // Continue from the progress saved by the previous run instead of starting over
+    pub resume: bool,
+    // Seed of the generated values that overrides the one from the config
+    pub seed: Option<u64>,
+    // Only preview masking without writing anything
+    pub dry_run: bool,
+    // Write masking statements to the script at this path instead of executing them
+    pub export: Option<PathBuf>,
+    // Write the draft config with the discovered personal data to this path
+    pub discover: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--resume" => parsed.resume = true,
+                "--dry-run" => parsed.dry_run = true,
+                "--seed" => match args.next().and_then(|s| s.parse::<u64>().ok()) {
+                    Some(seed) => parsed.seed = Some(seed),
+                    None => return Err(String::from("--seed requires a non-negative number")),
+                },
+                "--export" => match args.next() {
+                    Some(path) => parsed.export = Some(PathBuf::from(path)),
+                    None => return Err(String::from("--export requires the path of the script")),
+                },
+                "--discover" => match args.next() {
+                    Some(path) => parsed.discover = Some(PathBuf::from(path)),
+                    None => return Err(String::from("--discover requires the path of the draft")),
+                },
+                unknown => return Err(format!("unknown argument {unknown}")),
+            }
+        }
+        // Only masking the DB saves the progress, so there's nothing to resume for the other modes
+        let modes = [
+            parsed.resume,
+            parsed.dry_run,
+            parsed.export.is_some(),
+            parsed.discover.is_some(),
+        ];
+        if modes.iter().filter(|m| **m).count() > 1 {
+            return Err(String::from(
+                "only one of --resume, --dry-run, --export and --discover can be used at a time",
+            ));
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn args_are_parsed() {
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+        assert_eq!(
+            parse(&["--resume"]).unwrap(),
+            Args {
+                resume: true,
+                ..Args::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--dry-run"]).unwrap(),
+            Args {
+                dry_run: true,
+                ..Args::default()
+            }
+        );
+    }
+
+    #[test]
+    fn seed_takes_a_number() {
+        assert_eq!(
+            parse(&["--seed", "42", "--dry-run"]).unwrap(),
+            Args {
+                seed: Some(42),
+                dry_run: true,
+                ..Args::default()
+            }
+        );
+        assert!(parse(&["--seed"]).is_err());
+        assert!(parse(&["--seed", "-1"]).is_err());
+    }
+
+    #[test]
+    fn export_takes_the_script_path() {
+        assert_eq!(
+            parse(&["--export", "mask.sql"]).unwrap(),
+            Args {
+                export: Some(PathBuf::from("mask.sql")),
+                ..Args::default()
+            }
+        );
+        assert!(parse(&["--export"]).is_err());
+        assert!(parse(&["--export", "mask.sql", "--dry-run"]).is_err());
+    }
+
+    #[test]
+    fn discover_takes_the_draft_path() {
+        assert_eq!(
+            parse(&["--discover", "draft.yaml"]).unwrap(),
+            Args {
+                discover: Some(PathBuf::from("draft.yaml")),
+                ..Args::default()
+            }
+        );
+        assert!(parse(&["--discover"]).is_err());
+        assert!(parse(&["--discover", "draft.yaml", "--export", "mask.sql"]).is_err());
+    }
+
+    #[test]
+    fn resume_only_applies_to_masking() {
+        assert!(parse(&["--resume", "--dry-run"]).is_err());
+        assert!(parse(&["--export", "mask.sql", "--resume"]).is_err());
+        assert!(parse(&["--resume", "--discover", "draft.yaml"]).is_err());
+    }
+
+    #[test]
+    fn unknown_args_are_rejected() {
+        assert!(parse(&["--resum"]).is_err());
+    }
+}
@@ -1,11 +1,37 @@
 use crate::masker::{error::ConfigParseError, Masker};
 use async_trait::async_trait;
 
-use super::{error::DatabaseAdapterError, mysql::MySQLAdapter};
+use super::{
+    checkpoint::Checkpoint, discovery::DiscoveredEntity, error::DatabaseAdapterError,
+    mysql::MySQLAdapter, postgres::PostgresAdapter, preview::EntityPreview, script::ScriptWriter,
+    sqlite::SQLiteAdapter,
+};
 
 #[async_trait]
 pub trait DatabaseAdapter {
-    async fn apply_mask(&self, masker: &Masker) -> Result<(), DatabaseAdapterError>;
+    // Every operation that takes the masker first reads the primary keys of the tables from the
+    // DB, filling in the name and type of any key left out of the config and checking the
+    // configured ones against the actual keys.
+    async fn apply_mask(
+        &self,
+        masker: &mut Masker,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), DatabaseAdapterError>;
+    // Writes the statements that mask the data to the script instead of executing them. DB is
+    // only read to find the rows to mask.
+    async fn export_mask(
+        &self,
+        masker: &mut Masker,
+        script: &ScriptWriter,
+    ) -> Result<(), DatabaseAdapterError>;
+    // Shows what masking would do without writing anything to the DB
+    async fn preview_mask(
+        &self,
+        masker: &mut Masker,
+    ) -> Result<Vec<EntityPreview>, DatabaseAdapterError>;
+    // Scans every table of the DB for columns that likely hold personal data, judging by their
+    // names and sampled values. DB is only read.
+    async fn discover(&self) -> Result<Vec<DiscoveredEntity>, DatabaseAdapterError>;
 }
 
 pub fn new_db_adapter_from_yaml(
@@ -17,6 +43,8 @@ pub fn new_db_adapter_from_yaml(
                 Some(f_str) => match f_str {
                     // Add adapters here
                     "mysql" => Ok(Box::new(MySQLAdapter::new_from_yaml(&yaml["db"])?)),
+                    "postgres" => Ok(Box::new(PostgresAdapter::new_from_yaml(&yaml["db"])?)),
+                    "sqlite" => Ok(Box::new(SQLiteAdapter::new_from_yaml(&yaml["db"])?)),
                     unknown_family => Err(ConfigParseError {
                         kind: crate::masker::error::ConfigParseErrorKind::UnexpectedFieldValue(
                             String::from(unknown_family),
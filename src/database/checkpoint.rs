@@ -0,0 +1,523 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
+
+use crate::database::error::DatabaseAdapterError;
+use crate::database::shared;
+use crate::masker::error::ConfigParseError;
+use crate::masker::generator::{encode_hex, GeneratedValue};
+use crate::masker::Entity;
+
+const DEFAULT_CHECKPOINT_FILE: &str = "masker_checkpoint.yaml";
+// State file is written at most this often while masking, and once more when it's over
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Progress {
+    // Every row up to and including the key has been masked and committed
+    MaskedUpTo(Vec<GeneratedValue>),
+    Done,
+}
+
+// Keeps track of the masking progress of every entity and persists it to the state file, so that
+// an interrupted run could be resumed instead of starting over. Progress is updated right after
+// the batch is committed, but written to the file every SAVE_INTERVAL at most, so at worst the
+// batches committed during the last interval are masked twice after resume, which is harmless.
+// Progress is saved along with the fingerprint of the entity config, since it only makes sense
+// for the same rows masked in the same way.
+pub struct Checkpoint {
+    path: Option<PathBuf>,
+    state: Mutex<BTreeMap<String, (String, Progress)>>,
+    // When the state file was last written. Held while the file is being written, so that the
+    // progress could still be updated meanwhile.
+    saved_at: Mutex<Option<Instant>>,
+}
+
+impl Checkpoint {
+    // Checkpoint without the path only keeps the progress in memory
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            state: Mutex::new(BTreeMap::new()),
+            saved_at: Mutex::new(None),
+        }
+    }
+
+    pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
+        let path = match yaml.as_mapping() {
+            Some(m) if m.contains_key("checkpoint_file") => {
+                shared::read_str_field(m, String::from("checkpoint_file"))?
+            }
+            _ => String::from(DEFAULT_CHECKPOINT_FILE),
+        };
+        Ok(Self::new(Some(PathBuf::from(path))))
+    }
+
+    // Loads the progress of the previous run. Missing state file means that the previous run
+    // hasn't committed anything yet, so masking starts from scratch.
+    pub fn resume(&self) -> Result<(), DatabaseAdapterError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(DatabaseAdapterError::invalid_checkpoint(format!(
+                    "couldn't read {}: {e}",
+                    path.display()
+                )))
+            }
+        };
+        let invalid = || {
+            DatabaseAdapterError::invalid_checkpoint(format!(
+                "{} has unexpected format",
+                path.display()
+            ))
+        };
+        let yaml: Value = serde_yaml::from_str(&content).map_err(|_| invalid())?;
+        let mut state = self.state.lock().unwrap();
+        for (entity, progress) in yaml.as_mapping().ok_or_else(invalid)? {
+            let entity = entity.as_str().ok_or_else(invalid)?;
+            let fingerprint = progress["fingerprint"].as_str().ok_or_else(invalid)?;
+            let progress = progress_from_yaml(progress).ok_or_else(invalid)?;
+            state.insert(entity.to_string(), (fingerprint.to_string(), progress));
+        }
+        Ok(())
+    }
+
+    pub fn get_progress(&self, entity: &Entity) -> Result<Option<Progress>, DatabaseAdapterError> {
+        let name = entity_key(entity);
+        let Some((saved, progress)) = self.state.lock().unwrap().get(&name).cloned() else {
+            return Ok(None);
+        };
+        if saved != fingerprint(entity) {
+            return Err(DatabaseAdapterError::invalid_checkpoint(format!(
+                "config of entity {name} has changed since the progress was saved"
+            )));
+        }
+        match &progress {
+            Progress::MaskedUpTo(key) if key.len() != entity.get_pk().len() => {
+                Err(DatabaseAdapterError::invalid_checkpoint(format!(
+                    "saved key of entity {name} doesn't match its primary key"
+                )))
+            }
+            _ => Ok(Some(progress)),
+        }
+    }
+
+    // Updates the progress of the entity and writes the state file, unless it has been written
+    // within SAVE_INTERVAL or is being written right now
+    pub fn save_progress(
+        &self,
+        entity: &Entity,
+        progress: Progress,
+    ) -> Result<(), DatabaseAdapterError> {
+        self.state
+            .lock()
+            .unwrap()
+            .insert(entity_key(entity), (fingerprint(entity), progress));
+        let Ok(saved_at) = self.saved_at.try_lock() else {
+            return Ok(());
+        };
+        if saved_at.is_some_and(|t| t.elapsed() < SAVE_INTERVAL) {
+            return Ok(());
+        }
+        self.write(saved_at)
+    }
+
+    // Writes the state file with every update, once masking is over
+    pub fn flush(&self) -> Result<(), DatabaseAdapterError> {
+        self.write(self.saved_at.lock().unwrap())
+    }
+
+    fn write(
+        &self,
+        mut saved_at: MutexGuard<'_, Option<Instant>>,
+    ) -> Result<(), DatabaseAdapterError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let yaml: Mapping = self
+            .state
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(entity, (fingerprint, progress))| {
+                let mut m = progress_to_yaml(progress);
+                m.insert(
+                    Value::from("fingerprint"),
+                    Value::from(fingerprint.as_str()),
+                );
+                (Value::from(entity.as_str()), Value::Mapping(m))
+            })
+            .collect();
+        let content = serde_yaml::to_string(&yaml).expect("checkpoint is always serializable");
+        // State file is replaced atomically, so that it's never left half written if the process
+        // gets killed in the middle of saving. Content is flushed to the disk before the rename,
+        // otherwise the renamed file could turn out empty after a crash of the whole machine.
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        std::fs::File::create(&tmp_path)
+            .and_then(|mut f| {
+                f.write_all(content.as_bytes())?;
+                f.sync_all()
+            })
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(DatabaseAdapterError::failed_to_save_checkpoint)?;
+        *saved_at = Some(Instant::now());
+        Ok(())
+    }
+}
+
+// Progress is saved by the table name, while the entities masking the same table after the first
+// one get their position appended (e.g. customers#2)
+fn entity_key(entity: &Entity) -> String {
+    match entity.get_ordinal() {
+        1 => entity.get_table_name(),
+        n => format!("{}#{n}", entity.get_table_name()),
+    }
+}
+
+// Covers the parts of the config the saved progress depends on: the table, its key, the row
+// filter and the whole config of every masked column
+fn fingerprint(entity: &Entity) -> String {
+    let config = std::iter::once(entity.get_table_name())
+        .chain(entity.get_pk().iter().map(|c| c.get_name().clone()))
+        .chain(std::iter::once(
+            entity.get_filter().cloned().unwrap_or_default(),
+        ))
+        .chain(
+            entity
+                .get_entries()
+                .iter()
+                .map(|f| format!("{}:{:?}:{}", f.get_column_name(), f.kind, f.get_config())),
+        )
+        .collect::<Vec<String>>()
+        .join("\0");
+    encode_hex(&Sha256::digest(config.as_bytes()))
+}
+
+fn progress_to_yaml(progress: &Progress) -> Mapping {
+    let mut m = Mapping::new();
+    match progress {
+        Progress::Done => {
+            m.insert(Value::from("done"), Value::from(true));
+        }
+        Progress::MaskedUpTo(key) => {
+            let key = key
+                .iter()
+                .map(|val| {
+                    let (kind, val) = match val {
+                        GeneratedValue::Number(n) => ("number", n.clone()),
+                        GeneratedValue::String(s) => ("string", s.clone()),
+                        GeneratedValue::Bytes(b) => ("bytes", encode_hex(b)),
+                        GeneratedValue::Null => ("null", String::new()),
+                    };
+                    Value::Mapping(Mapping::from_iter([(Value::from(kind), Value::from(val))]))
+                })
+                .collect();
+            m.insert(Value::from("last_key"), Value::Sequence(key));
+        }
+    }
+    m
+}
+
+fn progress_from_yaml(yaml: &Value) -> Option<Progress> {
+    if yaml["done"].as_bool() == Some(true) {
+        return Some(Progress::Done);
+    }
+    let key = yaml["last_key"]
+        .as_sequence()?
+        .iter()
+        .map(|val| {
+            let (kind, val) = val.as_mapping()?.iter().next()?;
+            let val = val.as_str()?.to_string();
+            match kind.as_str()? {
+                "number" => Some(GeneratedValue::Number(val)),
+                "string" => Some(GeneratedValue::String(val)),
+                "bytes" => decode_hex(&val).map(GeneratedValue::Bytes),
+                "null" => Some(GeneratedValue::Null),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<GeneratedValue>>>()?;
+    Some(Progress::MaskedUpTo(key))
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use sqlx::Row;
+
+    use super::*;
+    use crate::database::error::DatabaseAdapterErrorKind;
+    use crate::database::script::Output;
+    use crate::database::sqlite::test_db::{get_adapter, get_test_conn};
+    use crate::masker::generator::{Locale, Options, SimpleGenerator};
+    use crate::masker::{Field, FieldKind, Masker, PkColumn, PkType};
+
+    fn get_entity(t_name: &str, pk_len: usize) -> Entity {
+        Entity::new(
+            t_name.to_string(),
+            (0..pk_len)
+                .map(|i| PkColumn::new(format!("id{i}"), PkType::Int))
+                .collect(),
+            vec![],
+        )
+    }
+
+    fn get_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("masker_{}_{name}.yaml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn checkpoint_restores_saved_progress() {
+        let path = get_path("restore");
+        let payments = get_entity("payments", 3);
+        let key = vec![
+            GeneratedValue::Number("103".to_string()),
+            GeneratedValue::String("HQ: 336'336".to_string()),
+            GeneratedValue::Bytes(vec![0, 171, 255]),
+        ];
+        let saved = Checkpoint::new(Some(path.clone()));
+        saved
+            .save_progress(&payments, Progress::MaskedUpTo(key.clone()))
+            .unwrap();
+        saved
+            .save_progress(&get_entity("customers", 1), Progress::Done)
+            .unwrap();
+        // Second update comes within the interval, so it's only written once flushed
+        let restored = Checkpoint::new(Some(path.clone()));
+        restored.resume().unwrap();
+        assert_eq!(
+            restored.get_progress(&get_entity("customers", 1)).unwrap(),
+            None
+        );
+        saved.flush().unwrap();
+
+        let restored = Checkpoint::new(Some(path.clone()));
+        assert_eq!(restored.get_progress(&payments).unwrap(), None);
+        restored.resume().unwrap();
+        assert_eq!(
+            restored.get_progress(&payments).unwrap(),
+            Some(Progress::MaskedUpTo(key))
+        );
+        assert_eq!(
+            restored.get_progress(&get_entity("customers", 1)).unwrap(),
+            Some(Progress::Done)
+        );
+        assert_eq!(
+            restored.get_progress(&get_entity("offices", 1)).unwrap(),
+            None
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_starts_from_scratch_without_state_file() {
+        let checkpoint = Checkpoint::new(Some(get_path("missing")));
+        checkpoint.resume().unwrap();
+        assert_eq!(
+            checkpoint
+                .get_progress(&get_entity("customers", 1))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn checkpoint_rejects_key_that_doesnt_match_pk() {
+        let checkpoint = Checkpoint::new(None);
+        checkpoint
+            .save_progress(
+                &get_entity("payments", 1),
+                Progress::MaskedUpTo(vec![GeneratedValue::Number("1".to_string())]),
+            )
+            .unwrap();
+        let err = checkpoint
+            .get_progress(&get_entity("payments", 2))
+            .unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::InvalidCheckpoint(_)
+        ))
+    }
+
+    #[test]
+    fn checkpoint_rejects_progress_of_changed_entity() {
+        let path = get_path("changed");
+        let saved = Checkpoint::new(Some(path.clone()));
+        saved
+            .save_progress(&get_entity("payments", 1), Progress::Done)
+            .unwrap();
+
+        let restored = Checkpoint::new(Some(path.clone()));
+        restored.resume().unwrap();
+        assert_eq!(
+            restored.get_progress(&get_entity("payments", 1)).unwrap(),
+            Some(Progress::Done)
+        );
+        let filtered = get_entity("payments", 1).with_filter(String::from("id0 > 10"));
+        let err = restored.get_progress(&filtered).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::InvalidCheckpoint(_)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_rejects_progress_of_entity_with_changed_fields() {
+        let get_entity = |template: &str| {
+            let yaml =
+                serde_yaml::from_str(&format!("name: name\nkind: Template\ntemplate: {template}"))
+                    .unwrap();
+            Entity::new(
+                "payments".to_string(),
+                vec![PkColumn::new("id".to_string(), PkType::Int)],
+                vec![Field::new_from_yaml(&yaml, Locale::default()).unwrap()],
+            )
+        };
+        let checkpoint = Checkpoint::new(None);
+        checkpoint
+            .save_progress(&get_entity("masked"), Progress::Done)
+            .unwrap();
+        let err = checkpoint
+            .get_progress(&get_entity("anonymized"))
+            .unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::InvalidCheckpoint(_)
+        ));
+    }
+
+    #[test]
+    fn checkpoint_keeps_progress_of_every_entity_of_table() {
+        let path = get_path("same_table");
+        let masker = Masker::new(vec![
+            get_entity("payments", 1),
+            get_entity("payments", 1).with_filter(String::from("id0 > 10")),
+        ]);
+        let [first, second] = masker.get_entities().as_slice() else {
+            unreachable!()
+        };
+        let saved = Checkpoint::new(Some(path.clone()));
+        saved.save_progress(first, Progress::Done).unwrap();
+        let key = vec![GeneratedValue::Number("11".to_string())];
+        saved
+            .save_progress(second, Progress::MaskedUpTo(key.clone()))
+            .unwrap();
+        saved.flush().unwrap();
+
+        let restored = Checkpoint::new(Some(path.clone()));
+        restored.resume().unwrap();
+        assert_eq!(restored.get_progress(first).unwrap(), Some(Progress::Done));
+        assert_eq!(
+            restored.get_progress(second).unwrap(),
+            Some(Progress::MaskedUpTo(key))
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_rejects_malformed_state_file() {
+        let path = get_path("malformed");
+        std::fs::write(&path, "payments:\n  last_key:\n    - float: '1.5'\n").unwrap();
+        let err = Checkpoint::new(Some(path.clone())).resume().unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::InvalidCheckpoint(_)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_file_is_read_from_yaml() {
+        let yaml =
+            serde_yaml::from_str("family: sqlite\ncheckpoint_file: /tmp/state.yaml").unwrap();
+        let checkpoint = Checkpoint::new_from_yaml(&yaml).unwrap();
+        assert_eq!(checkpoint.path, Some(PathBuf::from("/tmp/state.yaml")));
+        let yaml = serde_yaml::from_str("family: sqlite").unwrap();
+        let checkpoint = Checkpoint::new_from_yaml(&yaml).unwrap();
+        assert_eq!(
+            checkpoint.path,
+            Some(PathBuf::from(DEFAULT_CHECKPOINT_FILE))
+        );
+    }
+
+    #[tokio::test]
+    async fn masking_resumes_from_checkpoint() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let fields: Vec<Field> = vec![Field::new(
+            "contactFirstName".to_string(),
+            FieldKind::Template,
+            Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                Ok(GeneratedValue::String("masked".to_string()))
+            })),
+        )];
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            fields,
+        );
+        let checkpoint = Checkpoint::new(None);
+        checkpoint
+            .save_progress(
+                &entity,
+                Progress::MaskedUpTo(vec![GeneratedValue::Number("112".to_string())]),
+            )
+            .unwrap();
+
+        shared::mask_table(&adapter, &entity, &pool, &checkpoint, Output::Database)
+            .await
+            .unwrap();
+        let masked: Vec<i64> =
+            sqlx::query("SELECT customerNumber FROM customers WHERE contactFirstName = 'masked'")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+                .iter()
+                .map(|r| r.get::<i64, _>(0))
+                .collect();
+        assert_eq!(masked, vec![114, 119]);
+        assert_eq!(
+            checkpoint.get_progress(&entity).unwrap(),
+            Some(Progress::Done)
+        );
+
+        // Entities that are done are skipped completely
+        sqlx::query("UPDATE customers SET contactFirstName = 'original'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        shared::mask_table(&adapter, &entity, &pool, &checkpoint, Output::Database)
+            .await
+            .unwrap();
+        let untouched =
+            sqlx::query("SELECT COUNT(*) FROM customers WHERE contactFirstName = 'original'")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .get::<i64, _>(0);
+        assert_eq!(untouched, 4)
+    }
+}
@@ -0,0 +1,670 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use iban::Iban;
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+
+use crate::database::schema::{classify_column_type, pk_type_from_sql, pk_type_name, ColumnClass};
+use crate::masker::generator::phone_country;
+
+// Number of rows of every table whose values are checked for personal data
+pub const SAMPLE_SIZE: i64 = 100;
+
+// Share of the sampled values that have to look like personal data of some kind for the column
+// to be flagged by its content.
+const MATCH_RATIO: f64 = 0.8;
+
+static EMAIL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[^@\s]+@[^@\s]+\.[A-Za-z]{2,}$").unwrap());
+// Only numbers in the international format are recognized by the content alone, since plain
+// digit sequences are just as likely to be some kind of reference number.
+static PHONE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\+[0-9][0-9 ()./-]{6,}$").unwrap());
+
+// Table as it's described by the catalog of the DB
+pub struct TableCatalog {
+    table: String,
+    // Name and SQL type of every column in the order of the table definition
+    columns: Vec<(String, String)>,
+    // Primary key columns in the order of the key
+    pk: Vec<String>,
+}
+
+impl TableCatalog {
+    pub fn new(table: String, columns: Vec<(String, String)>, pk: Vec<String>) -> Self {
+        Self { table, columns, pk }
+    }
+
+    // Builds the catalog of every table out of the rows of table name, column name, column type
+    // and the position of the column in the primary key (0 if it's not part of it). Rows of the
+    // same table are expected to go one after another.
+    pub fn from_rows(rows: Vec<(String, String, String, i64)>) -> Vec<Self> {
+        let mut tables: Vec<(Self, Vec<(i64, String)>)> = vec![];
+        for (table, column, sql_type, pk_pos) in rows {
+            if tables.last().is_none_or(|(t, _)| t.table != table) {
+                tables.push((Self::new(table, vec![], vec![]), vec![]));
+            }
+            let (t, pk) = tables.last_mut().unwrap();
+            if pk_pos > 0 {
+                pk.push((pk_pos, column.clone()));
+            }
+            t.columns.push((column, sql_type));
+        }
+        tables
+            .into_iter()
+            .map(|(mut t, mut pk)| {
+                pk.sort();
+                t.pk = pk.into_iter().map(|(_, c)| c).collect();
+                t
+            })
+            .collect()
+    }
+
+    pub fn get_table_name(&self) -> &String {
+        &self.table
+    }
+
+    // Columns which content is sampled. Only text columns could hold the values masker generates
+    // and key columns are never masked.
+    pub fn get_text_columns(&self) -> Vec<&String> {
+        self.columns
+            .iter()
+            .filter(|(c, t)| {
+                !self.pk.contains(c)
+                    && matches!(
+                        classify_column_type(t),
+                        ColumnClass::Text | ColumnClass::Untyped
+                    )
+            })
+            .map(|(c, _)| c)
+            .collect()
+    }
+
+    // Key as it should be written to the config, or the reason the table can't be masked
+    fn pk_yaml(&self) -> Result<Value, String> {
+        if self.pk.is_empty() {
+            return Err(String::from("it has no primary key"));
+        }
+        let pk = self
+            .pk
+            .iter()
+            .map(|c| {
+                let sql_type = &self.columns.iter().find(|(name, _)| name == c).unwrap().1;
+                let pk_type = pk_type_from_sql(sql_type).ok_or_else(|| {
+                    format!("type {sql_type} of its primary key column {c} isn't supported")
+                })?;
+                Ok(Value::Mapping(Mapping::from_iter([
+                    (Value::from("name"), Value::from(c.as_str())),
+                    (Value::from("type"), Value::from(pk_type_name(&pk_type))),
+                ])))
+            })
+            .collect::<Result<Vec<Value>, String>>()?;
+        match <[Value; 1]>::try_from(pk) {
+            Ok([column]) => Ok(column),
+            Err(pk) => Ok(Value::Sequence(pk)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum PersonalData {
+    FirstName,
+    LastName,
+    CityName,
+    CountryCode,
+    CountryName,
+    PostCode,
+    StateName,
+    Iban {
+        formatted: bool,
+        country_codes: Vec<String>,
+    },
+    Email,
+    PhoneNumber {
+        country: Option<String>,
+    },
+    FreeText,
+}
+
+impl PersonalData {
+    // Field of the draft config that masks the column. Kinds masker has no generator for are
+    // drafted as templates of the similar shape.
+    fn field_yaml(&self, column: &str) -> Value {
+        let mut m = Mapping::new();
+        m.insert(Value::from("name"), Value::from(column));
+        let (kind, template) = match self {
+            PersonalData::FirstName => ("FirstName", None),
+            PersonalData::LastName => ("LastName", None),
+            PersonalData::CityName => ("CityName", None),
+            PersonalData::CountryCode => ("CountryCode", None),
+            PersonalData::CountryName => ("CountryName", None),
+            PersonalData::PostCode => ("PostCode", None),
+            PersonalData::StateName => ("StateName", None),
+            PersonalData::Iban { .. } => ("Iban", None),
+            PersonalData::Email => ("Email", None),
+            PersonalData::PhoneNumber { .. } => ("PhoneNumber", None),
+            PersonalData::FreeText => ("Template", Some("%{Lllllll} %{llllll} %{lllllll}")),
+        };
+        m.insert(Value::from("kind"), Value::from(kind));
+        if let Some(template) = template {
+            m.insert(Value::from("template"), Value::from(template));
+        }
+        if let PersonalData::PhoneNumber {
+            country: Some(country),
+        } = self
+        {
+            m.insert(Value::from("country"), Value::from(country.as_str()));
+        }
+        if let PersonalData::Iban {
+            formatted,
+            country_codes,
+        } = self
+        {
+            m.insert(Value::from("formatted"), Value::from(*formatted));
+            m.insert(
+                Value::from("country_codes"),
+                Value::Sequence(
+                    country_codes
+                        .iter()
+                        .map(|c| Value::from(c.as_str()))
+                        .collect(),
+                ),
+            );
+        }
+        Value::Mapping(m)
+    }
+}
+
+impl Display for PersonalData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PersonalData::FirstName => "first name",
+            PersonalData::LastName => "last name",
+            PersonalData::CityName => "city",
+            PersonalData::CountryCode => "country code",
+            PersonalData::CountryName => "country",
+            PersonalData::PostCode => "postcode",
+            PersonalData::StateName => "state",
+            PersonalData::Iban { .. } => "IBAN",
+            PersonalData::Email => "email",
+            PersonalData::PhoneNumber { .. } => "phone number",
+            PersonalData::FreeText => "free text",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// Splits the column name into lower case words, both snake_case and camelCase names are
+// supported.
+fn name_words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if !c.is_alphanumeric() {
+            prev_lower = false;
+            words.push(String::new());
+            continue;
+        }
+        if (c.is_uppercase() && prev_lower) || words.is_empty() {
+            words.push(String::new());
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        words.last_mut().unwrap().extend(c.to_lowercase());
+    }
+    words.retain(|w| !w.is_empty());
+    words
+}
+
+fn guess_from_name(column: &str) -> Option<PersonalData> {
+    let words = name_words(column);
+    let joined = words.concat();
+    let has = |candidates: &[&str]| words.iter().any(|w| candidates.contains(&w.as_str()));
+    let joined_has = |candidates: &[&str]| candidates.iter().any(|c| joined.contains(c));
+    if has(&["iban"]) {
+        Some(PersonalData::Iban {
+            formatted: false,
+            country_codes: vec![],
+        })
+    } else if has(&["mail"]) || joined_has(&["email"]) {
+        Some(PersonalData::Email)
+    } else if has(&["mobile", "tel", "fax"]) || joined_has(&["phone"]) {
+        Some(PersonalData::PhoneNumber { country: None })
+    } else if has(&["fname"]) || joined_has(&["firstname", "givenname", "forename"]) {
+        Some(PersonalData::FirstName)
+    } else if has(&["lname"]) || joined_has(&["lastname", "surname", "familyname"]) {
+        Some(PersonalData::LastName)
+    } else if has(&["city", "town"]) {
+        Some(PersonalData::CityName)
+    } else if has(&["country"]) && has(&["code", "iso"]) {
+        Some(PersonalData::CountryCode)
+    } else if has(&["country"]) {
+        Some(PersonalData::CountryName)
+    } else if has(&["zip"]) || joined_has(&["postcode", "postalcode", "zipcode"]) {
+        Some(PersonalData::PostCode)
+    // "state" is as often the status of the row, so only the column named exactly like that is
+    // taken for the address
+    } else if joined == "state" || joined == "province" {
+        Some(PersonalData::StateName)
+    } else if joined_has(&["address", "comment", "note", "remark", "description"]) {
+        Some(PersonalData::FreeText)
+    } else {
+        None
+    }
+}
+
+fn parse_iban(s: &str) -> Option<Iban> {
+    let compact: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    Iban::from_str(&compact).ok()
+}
+
+// Content is checked for the kinds of data that have a recognizable format
+fn guess_from_samples(values: &[&str]) -> Option<(PersonalData, usize)> {
+    let matches = |is_match: &dyn Fn(&str) -> bool| values.iter().filter(|v| is_match(v)).count();
+    let is_enough = |n: usize| !values.is_empty() && n as f64 >= values.len() as f64 * MATCH_RATIO;
+    let ibans = matches(&|v| parse_iban(v).is_some());
+    if is_enough(ibans) {
+        return Some((iban_from_samples(values), ibans));
+    }
+    let emails = matches(&|v| EMAIL_RE.is_match(v));
+    if is_enough(emails) {
+        return Some((PersonalData::Email, emails));
+    }
+    let phones = matches(&|v| PHONE_RE.is_match(v));
+    if is_enough(phones) {
+        return Some((phone_from_samples(values), phones));
+    }
+    None
+}
+
+// Generated IBANs follow the format and the countries of the existing ones
+fn iban_from_samples(values: &[&str]) -> PersonalData {
+    let ibans: Vec<&str> = values
+        .iter()
+        .copied()
+        .filter(|v| parse_iban(v).is_some())
+        .collect();
+    let mut country_codes: Vec<String> = ibans
+        .iter()
+        .filter_map(|v| v.trim().get(..2))
+        .map(String::from)
+        .collect();
+    country_codes.sort();
+    country_codes.dedup();
+    if country_codes.is_empty() {
+        country_codes = iban::get_supported_countries()
+            .into_iter()
+            .map(String::from)
+            .collect();
+    }
+    PersonalData::Iban {
+        formatted: ibans.iter().filter(|v| v.trim().contains(' ')).count() * 2 > ibans.len(),
+        country_codes,
+    }
+}
+
+// Generated phone numbers follow the format of the country most of the existing ones are from
+fn phone_from_samples(values: &[&str]) -> PersonalData {
+    let mut countries: Vec<&str> = values.iter().filter_map(|v| phone_country(v)).collect();
+    countries.sort();
+    let country = countries
+        .chunk_by(|a, b| a == b)
+        .max_by_key(|c| c.len())
+        .map(|c| String::from(c[0]));
+    PersonalData::PhoneNumber { country }
+}
+
+// Long values of several words are likely written by people and might mention anyone
+fn looks_like_free_text(values: &[&str]) -> usize {
+    values
+        .iter()
+        .filter(|v| v.split_whitespace().count() >= 3)
+        .count()
+}
+
+#[derive(Debug, PartialEq)]
+struct Finding {
+    column: String,
+    data: PersonalData,
+    reason: String,
+}
+
+fn find_personal_data(column: &str, samples: &[Option<String>]) -> Option<Finding> {
+    let values: Vec<&str> = samples
+        .iter()
+        .flatten()
+        .map(|v| v.as_str())
+        .filter(|v| !v.trim().is_empty())
+        .collect();
+    let sampled = |n: usize| format!("{n} of {} sampled values", values.len());
+    let finding = |data: PersonalData, reason: String| Finding {
+        column: column.to_string(),
+        data,
+        reason,
+    };
+    if let Some((data, n)) = guess_from_samples(&values) {
+        return Some(finding(data, sampled(n)));
+    }
+    if let Some(data) = guess_from_name(column) {
+        let data = match data {
+            PersonalData::Iban { .. } => iban_from_samples(&values),
+            PersonalData::PhoneNumber { .. } => phone_from_samples(&values),
+            other => other,
+        };
+        return Some(finding(data, String::from("column name")));
+    }
+    let texts = looks_like_free_text(&values);
+    if !values.is_empty() && texts as f64 >= values.len() as f64 * MATCH_RATIO {
+        return Some(finding(PersonalData::FreeText, sampled(texts)));
+    }
+    None
+}
+
+// Columns of the table that likely hold personal data, along with the reason they were flagged
+pub struct DiscoveredEntity {
+    table: String,
+    pk: Result<Value, String>,
+    findings: Vec<Finding>,
+}
+
+impl DiscoveredEntity {
+    // Samples are the rows of values of the text columns of the table, read as text (None stands
+    // for NULL). Tables that don't seem to have any personal data are skipped.
+    pub fn new(catalog: &TableCatalog, samples: Vec<Vec<Option<String>>>) -> Option<Self> {
+        let findings: Vec<Finding> = catalog
+            .get_text_columns()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, column)| {
+                let values: Vec<Option<String>> =
+                    samples.iter().map(|row| row[i].clone()).collect();
+                find_personal_data(column, &values)
+            })
+            .collect();
+        if findings.is_empty() {
+            return None;
+        }
+        Some(Self {
+            table: catalog.get_table_name().clone(),
+            pk: catalog.pk_yaml(),
+            findings,
+        })
+    }
+
+    fn schema_yaml(&self) -> Option<Value> {
+        let pk = self.pk.as_ref().ok()?;
+        Some(Value::Mapping(Mapping::from_iter([
+            (Value::from("table"), Value::from(self.table.as_str())),
+            (Value::from("pk"), pk.clone()),
+            (
+                Value::from("fields"),
+                Value::Sequence(
+                    self.findings
+                        .iter()
+                        .map(|f| f.data.field_yaml(&f.column))
+                        .collect(),
+                ),
+            ),
+        ])))
+    }
+}
+
+impl Display for DiscoveredEntity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.pk {
+            Ok(_) => writeln!(f, "{}", self.table)?,
+            Err(reason) => writeln!(f, "{} (left out of the draft, {reason})", self.table)?,
+        }
+        for finding in self.findings.iter() {
+            writeln!(
+                f,
+                "  {}: {} ({})",
+                finding.column, finding.data, finding.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Draft config that masks every discovered entity of the DB described by db_yaml. It's meant to
+// be reviewed before use, since discovery can only guess. Password is replaced with one read from
+// the environment, so that the draft can be shared without leaking it.
+pub fn draft_schema(db_yaml: &Value, entities: &[DiscoveredEntity]) -> String {
+    let mut db_yaml = db_yaml.clone();
+    if let Some(password) = db_yaml
+        .get_mut("connection")
+        .and_then(|c| c.get_mut("password"))
+    {
+        *password = Value::Mapping(Mapping::from_iter([(
+            Value::from("fromEnvKey"),
+            Value::from("DB_PASSWORD"),
+        )]));
+    }
+    let yaml = Mapping::from_iter([
+        (Value::from("db"), db_yaml),
+        (
+            Value::from("schemas"),
+            Value::Sequence(entities.iter().filter_map(|e| e.schema_yaml()).collect()),
+        ),
+    ]);
+    serde_yaml::to_string(&yaml).expect("draft is always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::shared::Dialect;
+    use crate::database::sqlite::test_db::{get_adapter, get_test_conn};
+    use crate::masker::Masker;
+
+    fn get_catalog() -> TableCatalog {
+        TableCatalog::from_rows(
+            [
+                ("customers", "id", "INTEGER", 1),
+                ("customers", "contactFirstName", "varchar(50)", 0),
+                ("customers", "contact_email", "TEXT", 0),
+                ("customers", "iban", "TEXT", 0),
+                ("customers", "notes", "TEXT", 0),
+                ("customers", "status", "TEXT", 0),
+                ("customers", "creditLimit", "numeric", 0),
+            ]
+            .map(|(t, c, s, pk)| (t.to_string(), c.to_string(), s.to_string(), pk))
+            .into(),
+        )
+        .pop()
+        .unwrap()
+    }
+
+    fn row(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values.iter().map(|v| v.map(String::from)).collect()
+    }
+
+    #[test]
+    fn catalog_is_built_from_rows() {
+        let catalogs = TableCatalog::from_rows(
+            [
+                ("orders", "id", "int", 1),
+                ("payments", "checkNumber", "varchar(50)", 2),
+                ("payments", "customerNumber", "int", 1),
+                ("payments", "comment", "text", 0),
+            ]
+            .map(|(t, c, s, pk)| (t.to_string(), c.to_string(), s.to_string(), pk))
+            .into(),
+        );
+        assert_eq!(catalogs.len(), 2);
+        assert_eq!(catalogs[1].pk, vec!["customerNumber", "checkNumber"]);
+        assert_eq!(catalogs[1].get_text_columns(), vec!["comment"]);
+        assert_eq!(
+            serde_yaml::to_string(&catalogs[1].pk_yaml().unwrap()).unwrap(),
+            "- name: customerNumber\n  type: int\n- name: checkNumber\n  type: string\n"
+        );
+    }
+
+    #[test]
+    fn column_names_are_split_into_words() {
+        assert_eq!(
+            name_words("contactFirstName"),
+            vec!["contact", "first", "name"]
+        );
+        assert_eq!(name_words("billing_ZIP"), vec!["billing", "zip"]);
+        assert_eq!(name_words("addressLine1"), vec!["address", "line1"]);
+    }
+
+    #[test]
+    fn personal_data_is_found_by_name_and_content() {
+        let samples = vec![
+            row(&[
+                Some("Carine"),
+                Some("carine@example.org"),
+                Some("DE89 3704 0044 0532 0130 00"),
+                Some("called twice about the delivery"),
+                Some("active"),
+            ]),
+            row(&[
+                None,
+                Some("jean@example.org"),
+                Some("FR14 2004 1010 0505 0001 3M02 606"),
+                Some("prefers email"),
+                Some("blocked"),
+            ]),
+        ];
+        let entity = DiscoveredEntity::new(&get_catalog(), samples).unwrap();
+        assert_eq!(
+            entity.to_string(),
+            "customers
+  contactFirstName: first name (column name)
+  contact_email: email (2 of 2 sampled values)
+  iban: IBAN (2 of 2 sampled values)
+  notes: free text (column name)
+"
+        );
+        assert_eq!(
+            entity.findings[2].data,
+            PersonalData::Iban {
+                formatted: true,
+                country_codes: vec!["DE".to_string(), "FR".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn phone_numbers_follow_the_country_of_samples() {
+        let samples = row(&[
+            Some("+49 151 23456789"),
+            Some("+49 30 1234567"),
+            Some("+33 6 12 34 56 78"),
+        ]);
+        let finding = find_personal_data("msisdn", &samples).unwrap();
+        assert_eq!(
+            finding.data,
+            PersonalData::PhoneNumber {
+                country: Some("DE".to_string())
+            }
+        );
+        assert_eq!(
+            serde_yaml::to_string(&finding.data.field_yaml("msisdn")).unwrap(),
+            "name: msisdn\nkind: PhoneNumber\ncountry: DE\n"
+        );
+        let finding = find_personal_data("phone", &[None]).unwrap();
+        assert_eq!(finding.data, PersonalData::PhoneNumber { country: None });
+    }
+
+    #[test]
+    fn table_without_personal_data_is_skipped() {
+        let catalog = TableCatalog::new(
+            "statuses".to_string(),
+            vec![
+                ("id".to_string(), "int".to_string()),
+                ("code".to_string(), "varchar(10)".to_string()),
+            ],
+            vec!["id".to_string()],
+        );
+        assert!(DiscoveredEntity::new(&catalog, vec![row(&[Some("NEW")])]).is_none());
+    }
+
+    #[test]
+    fn table_without_pk_is_reported_but_left_out_of_draft() {
+        let catalog = TableCatalog::new(
+            "audit".to_string(),
+            vec![("email".to_string(), "text".to_string())],
+            vec![],
+        );
+        let entity = DiscoveredEntity::new(&catalog, vec![]).unwrap();
+        assert_eq!(
+            entity.to_string(),
+            "audit (left out of the draft, it has no primary key)\n  email: email (column name)\n"
+        );
+        let draft = draft_schema(&Value::Null, &[entity]);
+        assert_eq!(draft, "db: null\nschemas: []\n");
+    }
+
+    #[test]
+    fn draft_is_a_valid_config() {
+        let samples = vec![row(&[
+            Some("Carine"),
+            Some("carine@example.org"),
+            Some("DE89370400440532013000"),
+            None,
+            None,
+        ])];
+        let entity = DiscoveredEntity::new(&get_catalog(), samples).unwrap();
+        let db_yaml = serde_yaml::from_str("family: sqlite\nconnection:\n  path: test.db").unwrap();
+        let draft = draft_schema(&db_yaml, &[entity]);
+        let yaml: Value = serde_yaml::from_str(&draft).unwrap();
+        assert_eq!(yaml["db"], db_yaml);
+        let masker = Masker::new_from_yaml(&yaml).unwrap();
+        let fields = masker.get_entities()[0].get_entries();
+        assert_eq!(fields.len(), 4);
+    }
+
+    #[test]
+    fn draft_leaves_out_the_password() {
+        let db_yaml: Value = serde_yaml::from_str(
+            "family: mysql\nconnection:\n  username: root\n  password: hunter2\n  host: localhost\n  port: 3306\n  db_name: shop",
+        )
+        .unwrap();
+        let draft = draft_schema(&db_yaml, &[]);
+        assert!(!draft.contains("hunter2"));
+        let yaml: Value = serde_yaml::from_str(&draft).unwrap();
+        assert_eq!(
+            yaml["db"]["connection"]["password"]["fromEnvKey"],
+            Value::from("DB_PASSWORD")
+        );
+        assert_eq!(
+            yaml["db"]["connection"]["host"],
+            db_yaml["connection"]["host"]
+        );
+    }
+
+    #[tokio::test]
+    async fn tables_are_sampled_for_discovery() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let catalogs = adapter.get_catalog(&pool).await.unwrap();
+        assert_eq!(
+            catalogs
+                .iter()
+                .map(|c| c.get_table_name().as_str())
+                .collect::<Vec<&str>>(),
+            vec!["customers", "payments"]
+        );
+        assert_eq!(catalogs[1].get_text_columns(), vec!["comment"]);
+        let samples = adapter
+            .get_column_samples(&catalogs[0], &pool, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            samples,
+            vec![
+                vec![Some("Carine".to_string())],
+                vec![Some("Jean".to_string())]
+            ]
+        );
+        let discovered = DiscoveredEntity::new(&catalogs[0], samples).unwrap();
+        assert_eq!(
+            discovered.to_string(),
+            "customers\n  contactFirstName: first name (column name)\n"
+        );
+    }
+}
@@ -10,6 +10,13 @@ pub enum DatabaseAdapterErrorKind {
     QueryFailed(sqlx::Error),
     DatabaseConnectionError(sqlx::Error),
     InconsistentSchema(String),
+    SchemaMismatch(Vec<String>),
+    ValueDoesntFitColumn(String, String),
+    InvalidIdentifier(String),
+    InvalidCheckpoint(String),
+    FailedToSaveCheckpoint(std::io::Error),
+    FailedToWriteScript(std::io::Error),
+    MissingParameter(String),
 }
 
 #[derive(Debug)]
@@ -26,6 +33,13 @@ impl Display for DatabaseAdapterError {
             DatabaseAdapterErrorKind::QueryFailed(e) => write!(f, "query has failed: {e}"),
             DatabaseAdapterErrorKind::DatabaseConnectionError(_) => write!(f, "connection failed"),
             DatabaseAdapterErrorKind::InconsistentSchema(missing_t) => write!(f, "some entities that were defined in yaml config were not found in the actual DB: {}", missing_t),
+            DatabaseAdapterErrorKind::SchemaMismatch(mismatches) => write!(f, "config doesn't match the columns of the actual DB:\n  {}", mismatches.join("\n  ")),
+            DatabaseAdapterErrorKind::ValueDoesntFitColumn(column, reason) => write!(f, "generated value doesn't fit column {column}: {reason}"),
+            DatabaseAdapterErrorKind::InvalidIdentifier(ident) => write!(f, "couldn't safely quote identifier {}", ident),
+            DatabaseAdapterErrorKind::InvalidCheckpoint(reason) => write!(f, "couldn't resume from checkpoint: {}", reason),
+            DatabaseAdapterErrorKind::FailedToSaveCheckpoint(e) => write!(f, "couldn't save progress to checkpoint file: {e}"),
+            DatabaseAdapterErrorKind::FailedToWriteScript(e) => write!(f, "couldn't write statements to the script: {e}"),
+            DatabaseAdapterErrorKind::MissingParameter(placeholder) => write!(f, "statement has no parameter for placeholder {placeholder}"),
         }
     }
 }
@@ -38,6 +52,13 @@ impl std::error::Error for DatabaseAdapterError {
             DatabaseAdapterErrorKind::QueryFailed(e) => Some(e),
             DatabaseAdapterErrorKind::DatabaseConnectionError(e) => Some(e),
             DatabaseAdapterErrorKind::InconsistentSchema(_) => None,
+            DatabaseAdapterErrorKind::SchemaMismatch(_) => None,
+            DatabaseAdapterErrorKind::ValueDoesntFitColumn(_, _) => None,
+            DatabaseAdapterErrorKind::InvalidIdentifier(_) => None,
+            DatabaseAdapterErrorKind::InvalidCheckpoint(_) => None,
+            DatabaseAdapterErrorKind::FailedToSaveCheckpoint(e) => Some(e),
+            DatabaseAdapterErrorKind::FailedToWriteScript(e) => Some(e),
+            DatabaseAdapterErrorKind::MissingParameter(_) => None,
         }
     }
 
@@ -67,4 +88,39 @@ impl DatabaseAdapterError {
             kind: DatabaseAdapterErrorKind::InconsistentSchema(missing_entity),
         }
     }
+    pub fn schema_mismatch(mismatches: Vec<String>) -> Self {
+        Self {
+            kind: DatabaseAdapterErrorKind::SchemaMismatch(mismatches),
+        }
+    }
+    pub fn value_doesnt_fit_column(column: String, reason: String) -> Self {
+        Self {
+            kind: DatabaseAdapterErrorKind::ValueDoesntFitColumn(column, reason),
+        }
+    }
+    pub fn invalid_identifier(ident: String) -> Self {
+        Self {
+            kind: DatabaseAdapterErrorKind::InvalidIdentifier(ident),
+        }
+    }
+    pub fn invalid_checkpoint(reason: String) -> Self {
+        Self {
+            kind: DatabaseAdapterErrorKind::InvalidCheckpoint(reason),
+        }
+    }
+    pub fn failed_to_save_checkpoint(error: std::io::Error) -> Self {
+        Self {
+            kind: DatabaseAdapterErrorKind::FailedToSaveCheckpoint(error),
+        }
+    }
+    pub fn failed_to_write_script(error: std::io::Error) -> Self {
+        Self {
+            kind: DatabaseAdapterErrorKind::FailedToWriteScript(error),
+        }
+    }
+    pub fn missing_parameter(placeholder: String) -> Self {
+        Self {
+            kind: DatabaseAdapterErrorKind::MissingParameter(placeholder),
+        }
+    }
 }
@@ -1,6 +1,21 @@
 mod adapter;
+mod checkpoint;
+mod discovery;
 pub mod error;
 mod mysql;
-mod shared;
+mod postgres;
+mod preview;
+mod scheduler;
+mod schema;
+mod script;
+pub mod shared;
+mod sqlite;
 
 pub use adapter::new_db_adapter_from_yaml;
+pub use checkpoint::Checkpoint;
+pub use discovery::draft_schema;
+#[cfg(test)]
+pub use script::Output;
+pub use script::ScriptWriter;
+#[cfg(test)]
+pub use sqlite::test_db;
@@ -1,195 +1,521 @@
 use super::credentials::MySQLConnectionCredentials;
 use async_trait::async_trait;
-use futures::future::join_all;
-use sqlx::Row;
-use std::collections::HashMap;
+use sqlx::mysql::{MySqlArguments, MySqlRow};
+use sqlx::query::Query;
+use sqlx::{Connection, MySql, Row, TypeInfo, ValueRef};
 
-use crate::database::adapter::DatabaseAdapter;
-use crate::database::error::{DatabaseAdapterError, DatabaseAdapterErrorKind};
+use crate::database::discovery::TableCatalog;
+use crate::database::error::DatabaseAdapterError;
+use crate::database::scheduler::Scheduler;
+use crate::database::schema::{Charset, ColumnInfo, TableColumns};
+use crate::database::script::{self, Statement};
+use crate::database::shared::{self, BatchRow, Dialect};
+use crate::masker;
 use crate::masker::error::ConfigParseError;
-use crate::masker::generator::GeneratedValue;
-use crate::masker::{self, Masker, PkType};
+use crate::masker::generator::{encode_hex, GeneratedValue};
+
+// Key is decoded based on the actual column type rather than the one specified in config, so that
+// wide (BIGINT UNSIGNED) and binary (BINARY(16) UUID) keys are read without overflows or
+// broken encoding.
+fn decode_key_value(r: &MySqlRow, i: usize) -> Result<GeneratedValue, sqlx::Error> {
+    let type_name = r.try_get_raw(i)?.type_info().name().to_string();
+    match type_name.as_str() {
+        "TINYINT UNSIGNED" | "SMALLINT UNSIGNED" | "MEDIUMINT UNSIGNED" | "INT UNSIGNED"
+        | "BIGINT UNSIGNED" => Ok(GeneratedValue::Number(r.try_get::<u64, _>(i)?.to_string())),
+        "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "BIGINT" => {
+            Ok(GeneratedValue::Number(r.try_get::<i64, _>(i)?.to_string()))
+        }
+        "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" => {
+            Ok(GeneratedValue::Bytes(r.try_get::<Vec<u8>, _>(i)?))
+        }
+        _ => Ok(GeneratedValue::String(r.try_get::<String, _>(i)?)),
+    }
+}
+
+// Numbers are bound as integers whenever they fit, since MySQL compares integer column with a
+// string by converting both sides to floating point, which loses precision for wide keys.
+fn bind_value<'q>(
+    query: Query<'q, MySql, MySqlArguments>,
+    val: &'q GeneratedValue,
+) -> Query<'q, MySql, MySqlArguments> {
+    match val {
+        GeneratedValue::Number(n) => match (n.parse::<i64>(), n.parse::<u64>()) {
+            (Ok(i), _) => query.bind(i),
+            (_, Ok(u)) => query.bind(u),
+            _ => query.bind(n.as_str()),
+        },
+        GeneratedValue::String(s) => query.bind(s.as_str()),
+        GeneratedValue::Bytes(b) => query.bind(b.as_slice()),
+        GeneratedValue::Null => query.bind(None::<String>),
+    }
+}
+
+// Renders the value as a literal of the exported script. Strings with backslashes are written as
+// hex literals, since whether backslash escapes anything depends on sql_mode of the session that
+// runs the script.
+fn literal(val: &GeneratedValue) -> String {
+    match val {
+        GeneratedValue::Number(n) if script::is_numeric_literal(n) => n.clone(),
+        GeneratedValue::Number(s) | GeneratedValue::String(s) if s.contains('\\') => {
+            format!("X'{}'", encode_hex(s.as_bytes()))
+        }
+        GeneratedValue::Number(s) | GeneratedValue::String(s) => script::quote_string_literal(s),
+        GeneratedValue::Bytes(b) => format!("X'{}'", encode_hex(b)),
+        GeneratedValue::Null => String::from("NULL"),
+    }
+}
+
+fn quote_identifier(ident: &str) -> String {
+    shared::quote_identifier_with(ident, '`')
+}
+
+fn quote_table_name(t_name: &str) -> String {
+    shared::quote_table_name_with(t_name, '`')
+}
+
+// Temporary table that holds masked values of the batch before they are written into the masked
+// table.
+const BATCH_TABLE: &str = "_masker_batch";
+
+// Columns without the charset are either binary or not text at all. Charsets other than the
+// listed ones are single or double byte, so only ASCII is known to be safe to write to them.
+fn charset_from_name(name: Option<&str>) -> Charset {
+    match name {
+        None | Some("binary") => Charset::Binary,
+        Some("utf8mb4" | "utf16" | "utf16le" | "utf32") => Charset::Utf8,
+        Some("utf8mb3" | "utf8" | "ucs2") => Charset::Utf8Bmp,
+        Some("latin1") => Charset::Latin1,
+        Some(_) => Charset::Ascii,
+    }
+}
 
 pub struct MySQLAdapter {
     connection_creds: MySQLConnectionCredentials,
+    scheduler: Scheduler,
 }
 
 impl MySQLAdapter {
     pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
         let connection_creds = MySQLConnectionCredentials::from_yaml(yaml)?;
-        Ok(MySQLAdapter { connection_creds })
+        let scheduler = Scheduler::new_from_yaml(yaml, 5)?;
+        Ok(MySQLAdapter {
+            connection_creds,
+            scheduler,
+        })
     }
 
-    async fn verify_entities(
+    // Statements that create the temporary table before the batch is written and drop it
+    // afterwards. Connection might come from the pool with the temporary table left by the failed
+    // batch, so it's dropped before being created as well.
+    fn batch_table_queries(
         &self,
-        masker: &Masker,
-        p: &sqlx::MySqlPool,
-    ) -> Result<(), DatabaseAdapterError> {
-        let rows = sqlx::query("SHOW TABLES;")
-            .fetch_all(p)
-            .await
-            .map_err(DatabaseAdapterError::failed_query)?;
-
-        let db_tables: Vec<String> = rows.iter().map(|r| r.get::<String, _>(0)).collect();
-
-        let mut missing_t = String::new();
-        if masker.get_entities().iter().all(|entity| -> bool {
-            let t_name = entity.get_table_name();
-            let check_res = db_tables.contains(&t_name);
-            if !check_res {
-                missing_t = t_name;
-            }
-            check_res
-        }) {
-            Ok(())
-        } else {
-            Err(DatabaseAdapterError::inconsistent_schema(missing_t))
-        }
+        masker_entity: &masker::Entity,
+        table_columns: &TableColumns,
+    ) -> Result<(Vec<String>, String), DatabaseAdapterError> {
+        let drop_query = format!(
+            "DROP TEMPORARY TABLE IF EXISTS {}",
+            quote_identifier(BATCH_TABLE)
+        );
+        Ok((
+            vec![
+                drop_query.clone(),
+                self.prepare_batch_table_query(masker_entity, table_columns)?,
+            ],
+            drop_query,
+        ))
     }
 
-    fn prepare_entity_query(
+    // Columns of the temporary table are declared with the types and collations of the masked
+    // table, so that the values get converted the same way before being written and the keys are
+    // compared the same way as in the masked table. Primary key lets the UPDATE ... JOIN look up
+    // every row of the batch by its key instead of scanning the whole temporary table.
+    fn prepare_batch_table_query(
         &self,
         masker_entity: &masker::Entity,
-        id: Box<dyn ToString>,
+        table_columns: &TableColumns,
     ) -> Result<String, DatabaseAdapterError> {
-        let entity_fields = masker_entity.get_entries();
-        if entity_fields.is_empty() {
-            return Err(DatabaseAdapterError {
-                kind: DatabaseAdapterErrorKind::NoEntriesSpecifiedForEntity(
-                    masker_entity.get_table_name(),
-                ),
-            });
-        }
-        let pk_name = masker_entity.get_pk_name();
-        let id = id.to_string();
-        let id_kv = match masker_entity.get_pk_type() {
-            PkType::Int => (pk_name, GeneratedValue::Number(id.clone())),
-            PkType::String => (pk_name, GeneratedValue::String(id.clone())),
-        };
-        let mut opts = HashMap::from([id_kv]);
-        for entry in entity_fields {
-            let val = entry.generate(&opts).map_err(|e| {
-                DatabaseAdapterError::failed_to_mask(String::from(entry.get_column_name()), e)
-            })?;
-            opts.insert(entry.get_column_name(), val);
-        }
-
-        let id_kv = opts.remove_entry(pk_name).unwrap(); // It's important to remove id from the
-                                                         // opts map before parsing it into query
-        let cond = format!("{} = {}", id_kv.0, id_kv.1);
-
+        let t_name = masker_entity.get_table_name();
+        let pk = masker_entity
+            .get_pk()
+            .iter()
+            .map(|c| c.get_name().as_str())
+            .collect::<Vec<&str>>();
+        let flags = shared::keep_flag_columns(masker_entity);
+        let columns = shared::batch_columns(masker_entity)
+            .iter()
+            .map(|c| {
+                if flags.contains(c) {
+                    return Ok(format!("{} BOOLEAN NOT NULL", quote_identifier(c)));
+                }
+                let column = table_columns.get(c).ok_or_else(|| {
+                    DatabaseAdapterError::inconsistent_schema(format!("{t_name}.{c}"))
+                })?;
+                let mut definition = format!("{} {}", quote_identifier(c), column.get_sql_type());
+                if let Some(collation) = column.get_collation() {
+                    definition = format!("{definition} COLLATE {}", quote_identifier(collation));
+                }
+                if pk.contains(&c.as_str()) {
+                    definition = format!("{definition} NOT NULL");
+                }
+                Ok(definition)
+            })
+            .collect::<Result<Vec<String>, DatabaseAdapterError>>()?;
         Ok(format!(
-            "UPDATE {} SET {} WHERE {}",
-            masker_entity.get_table_name(),
-            opts.iter()
-                .map(|(k, val)| format!("{} = {}", k, val))
+            "CREATE TEMPORARY TABLE {} ({}, PRIMARY KEY ({}))",
+            quote_identifier(BATCH_TABLE),
+            columns.join(", "),
+            pk.iter()
+                .map(|c| quote_identifier(c))
                 .collect::<Vec<String>>()
-                .join(", "),
-            cond
+                .join(", ")
         ))
     }
 
-    async fn get_batch_to_update(
+    fn prepare_batch_update_query(&self, masker_entity: &masker::Entity) -> String {
+        let cond = masker_entity
+            .get_pk()
+            .iter()
+            .map(|c| {
+                let column = quote_identifier(c.get_name());
+                format!("t.{} = b.{}", column, column)
+            })
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let set = masker_entity
+            .get_entries()
+            .iter()
+            .map(|f| {
+                let column = quote_identifier(f.get_column_name());
+                if f.has_keep_rules() {
+                    let flag = quote_identifier(&shared::keep_flag_column(f.get_column_name()));
+                    format!("t.{column} = CASE WHEN b.{flag} THEN t.{column} ELSE b.{column} END")
+                } else {
+                    format!("t.{} = b.{}", column, column)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!(
+            "UPDATE {} AS t JOIN {} AS b ON {} SET {}",
+            quote_table_name(&masker_entity.get_table_name()),
+            quote_identifier(BATCH_TABLE),
+            cond,
+            set
+        )
+    }
+}
+
+#[async_trait]
+impl Dialect for MySQLAdapter {
+    type Pool = sqlx::MySqlPool;
+    // MySQL protocol limits the number of placeholders of a prepared statement
+    const MAX_PARAMS: usize = 65535;
+
+    fn get_scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    // MySQL identifiers are limited to 64 characters from the Basic Multilingual Plane, can't contain
+    // NUL and can't end with space.
+    fn is_valid_identifier(ident: &str) -> bool {
+        !ident.is_empty()
+            && ident.chars().count() <= 64
+            && !ident.ends_with(' ')
+            && ident.chars().all(|c| c != '\0' && c <= '\u{FFFF}')
+    }
+
+    async fn connect(&self, read_only: bool) -> Result<Self::Pool, DatabaseAdapterError> {
+        sqlx::mysql::MySqlPoolOptions::new()
+            .max_connections(self.scheduler.get_pool_size())
+            .after_connect(move |conn, _| {
+                Box::pin(async move {
+                    if read_only {
+                        sqlx::query("SET SESSION TRANSACTION READ ONLY")
+                            .execute(conn)
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .connect(self.connection_creds.get_as_string().as_str())
+            .await
+            .map_err(DatabaseAdapterError::connection_error)
+    }
+
+    // Reads the types of the table columns, so that the config could be verified before any row
+    // gets masked, along with the length and charset the generated values have to fit and the
+    // position of the column in the primary key. Column type is read with its attributes, so
+    // that unsigned keys could be told apart. Table that isn't qualified with a database is
+    // looked up in the database of the connection.
+    async fn get_columns(
         &self,
         masker_entity: &masker::Entity,
-        p: &sqlx::MySqlPool,
-        b_size: i32,
-        offset: i32,
-    ) -> Result<Vec<String>, sqlx::Error> {
-        let values: Vec<String> = sqlx::query(
-            format!(
-                "SELECT {} FROM {} ORDER BY {} ASC LIMIT ? OFFSET ?",
-                masker_entity.get_pk_name(),
-                masker_entity.get_table_name(),
-                masker_entity.get_pk_name()
-            )
-            .as_str(),
+        p: &Self::Pool,
+    ) -> Result<TableColumns, sqlx::Error> {
+        let t_name = masker_entity.get_table_name();
+        let (schema, table) = shared::split_table_name(&t_name);
+        sqlx::query(
+            "SELECT CAST(c.column_name AS CHAR), CAST(c.column_type AS CHAR), \
+            CAST(c.character_maximum_length AS SIGNED), CAST(c.character_set_name AS CHAR), \
+            CAST(k.ordinal_position AS SIGNED), CAST(c.collation_name AS CHAR) \
+            FROM information_schema.columns AS c \
+            LEFT JOIN information_schema.key_column_usage AS k ON k.table_schema = c.table_schema \
+            AND k.table_name = c.table_name AND k.column_name = c.column_name \
+            AND k.constraint_name = 'PRIMARY' \
+            WHERE c.table_schema = COALESCE(?, DATABASE()) AND c.table_name = ?;",
         )
-        .bind(b_size)
-        .bind(offset)
+        .bind(schema)
+        .bind(table)
         .fetch_all(p)
         .await?
         .iter()
-        .map(|r| r.get::<i32, _>(0).to_string())
-        .collect();
-        Ok(values)
+        .map(|r| {
+            let max_length = r.try_get::<Option<i64>, _>(2)?.map(|l| l as usize);
+            let charset = charset_from_name(r.try_get::<Option<String>, _>(3)?.as_deref());
+            let pk_position = r.try_get::<Option<i64>, _>(4)?.map(|p| p as usize);
+            let column = ColumnInfo::new(r.try_get::<String, _>(1)?, max_length, charset)
+                .with_pk_position(pk_position)
+                .with_collation(r.try_get::<Option<String>, _>(5)?);
+            Ok((r.try_get::<String, _>(0)?, column))
+        })
+        .collect()
     }
 
     async fn get_total_size(
         &self,
         masker_entity: &masker::Entity,
-        p: &sqlx::MySqlPool,
-    ) -> Result<i32, sqlx::Error> {
-        Ok(sqlx::query(
-            format!(
-                "SELECT COUNT({}) FROM {};",
-                masker_entity.get_pk_name(),
-                masker_entity.get_table_name()
-            )
-            .as_str(),
+        p: &Self::Pool,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query(shared::count_query(masker_entity, quote_table_name).as_str())
+            .fetch_one(p)
+            .await?
+            .try_get(0)
+    }
+
+    async fn get_batch_to_update(
+        &self,
+        masker_entity: &masker::Entity,
+        p: &Self::Pool,
+        _: &TableColumns,
+        b_size: i64,
+        last_key: Option<&[GeneratedValue]>,
+    ) -> Result<Vec<BatchRow>, sqlx::Error> {
+        let pk = masker_entity.get_pk();
+        let pk_names = pk
+            .iter()
+            .map(|c| quote_identifier(c.get_name()))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let columns = masker_entity
+            .get_entries()
+            .iter()
+            .map(|f| format!(", CAST({} AS CHAR)", quote_identifier(f.get_column_name())))
+            .collect::<String>();
+        let conds = match last_key {
+            Some(key) => vec![format!(
+                "({}) > ({})",
+                pk_names,
+                vec!["?"; key.len()].join(", ")
+            )],
+            None => vec![],
+        };
+        let query_str = format!(
+            "SELECT {}{} FROM {} {}ORDER BY {} LIMIT ?",
+            pk_names,
+            columns,
+            quote_table_name(&masker_entity.get_table_name()),
+            shared::where_clause(masker_entity, &conds),
+            pk_names
+        );
+        let mut query = sqlx::query(query_str.as_str());
+        for val in last_key.unwrap_or_default() {
+            query = bind_value(query, val);
+        }
+        query
+            .bind(b_size)
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| {
+                let key = (0..pk.len())
+                    .map(|i| decode_key_value(r, i))
+                    .collect::<Result<Vec<GeneratedValue>, sqlx::Error>>()?;
+                let originals = (pk.len()..r.len())
+                    .map(|i| r.try_get::<Option<String>, _>(i))
+                    .collect::<Result<Vec<Option<String>>, sqlx::Error>>()?;
+                Ok((key, originals))
+            })
+            .collect()
+    }
+
+    // Describes every table of the current DB for discovery, along with the position of every
+    // column in the primary key. Column type is read with its attributes, so that unsigned keys
+    // could be told apart.
+    async fn get_catalog(&self, p: &Self::Pool) -> Result<Vec<TableCatalog>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT CAST(c.table_name AS CHAR), CAST(c.column_name AS CHAR), \
+            CAST(c.column_type AS CHAR), CAST(COALESCE(k.ordinal_position, 0) AS SIGNED) \
+            FROM information_schema.columns AS c \
+            JOIN information_schema.tables AS t ON t.table_schema = c.table_schema \
+            AND t.table_name = c.table_name AND t.table_type = 'BASE TABLE' \
+            LEFT JOIN information_schema.key_column_usage AS k ON k.table_schema = c.table_schema \
+            AND k.table_name = c.table_name AND k.column_name = c.column_name \
+            AND k.constraint_name = 'PRIMARY' \
+            WHERE c.table_schema = DATABASE() ORDER BY c.table_name, c.ordinal_position;",
         )
-        .fetch_one(p)
+        .fetch_all(p)
         .await?
-        .get::<i32, _>(0))
+        .iter()
+        .map(|r| Ok((r.try_get(0)?, r.try_get(1)?, r.try_get(2)?, r.try_get(3)?)))
+        .collect::<Result<Vec<(String, String, String, i64)>, sqlx::Error>>()?;
+        Ok(TableCatalog::from_rows(rows))
+    }
+
+    async fn get_column_values(
+        &self,
+        masker_entity: &masker::Entity,
+        column: &str,
+        p: &Self::Pool,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let column = quote_identifier(column);
+        let query_str = format!(
+            "SELECT DISTINCT CAST({} AS CHAR) FROM {} WHERE {} IS NOT NULL",
+            column,
+            quote_table_name(&masker_entity.get_table_name()),
+            column
+        );
+        sqlx::query(query_str.as_str())
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| r.try_get::<String, _>(0))
+            .collect()
+    }
+
+    async fn get_column_samples(
+        &self,
+        catalog: &TableCatalog,
+        p: &Self::Pool,
+        limit: i64,
+    ) -> Result<Vec<Vec<Option<String>>>, sqlx::Error> {
+        let columns = catalog.get_text_columns();
+        if columns.is_empty() {
+            return Ok(vec![]);
+        }
+        let query_str = format!(
+            "SELECT {} FROM {} LIMIT ?",
+            columns
+                .iter()
+                .map(|c| format!("CAST({} AS CHAR)", quote_identifier(c)))
+                .collect::<Vec<String>>()
+                .join(", "),
+            quote_identifier(catalog.get_table_name())
+        );
+        sqlx::query(query_str.as_str())
+            .bind(limit)
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| {
+                (0..r.len())
+                    .map(|i| r.try_get::<Option<String>, _>(i))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Rows are inserted into the temporary table, which is then joined with the masked table
+    fn prepare_batch_query(
+        &self,
+        masker_entity: &masker::Entity,
+        table_columns: &TableColumns,
+        batch: &[BatchRow],
+    ) -> Result<Statement, DatabaseAdapterError> {
+        let args = shared::generate_batch_values(masker_entity, table_columns, batch)?;
+        let columns = shared::batch_columns(masker_entity);
+        let row = format!("({})", vec!["?"; columns.len()].join(", "));
+        let query = format!(
+            "INSERT INTO {} ({}) VALUES {}",
+            quote_identifier(BATCH_TABLE),
+            columns
+                .iter()
+                .map(|c| quote_identifier(c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            vec![row; batch.len()].join(", ")
+        );
+
+        Ok((query, args))
     }
 
-    async fn mask_table(
+    // Every batch is first written into the temporary table with multi-row inserts and then
+    // applied to the masked table with a single UPDATE ... JOIN, so that writing a batch takes a
+    // few round trips instead of one per row. Temporary table is only visible to the connection
+    // that has created it, so the whole batch is written using a single connection.
+    async fn write_batch(
         &self,
         masker_entity: &masker::Entity,
-        p: &sqlx::MySqlPool,
+        p: &Self::Pool,
+        table_columns: &TableColumns,
+        statements: &[Statement],
     ) -> Result<(), DatabaseAdapterError> {
-        let sz_total = self
-            .get_total_size(masker_entity, p)
+        let (setup, drop_query) = self.batch_table_queries(masker_entity, table_columns)?;
+        let mut conn = p
+            .acquire()
             .await
             .map_err(DatabaseAdapterError::failed_query)?;
-        let b_size = 1000;
-        let iterations: f32 = sz_total as f32 / b_size as f32;
-        let futs = (0..iterations.ceil() as i32).map(move |offs_idx| async move {
-            let ids = self
-                .get_batch_to_update(masker_entity, p, b_size, offs_idx * b_size)
-                .await?;
-            if ids.is_empty() {
-                return Ok(());
+        for query_str in setup.iter() {
+            sqlx::query(query_str.as_str())
+                .execute(&mut *conn)
+                .await
+                .map_err(DatabaseAdapterError::failed_query)?;
+        }
+        let update = (self.prepare_batch_update_query(masker_entity), vec![]);
+        let mut tx = conn
+            .begin()
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        for (query_str, args) in statements.iter().chain([&update]) {
+            let mut query = sqlx::query(query_str.as_str());
+            for val in args.iter() {
+                query = bind_value(query, val);
             }
-            let mut tx = p.begin().await.unwrap();
-            for id in ids {
-                sqlx::query(
-                    self.prepare_entity_query(masker_entity, Box::new(id))
-                        .unwrap()
-                        .as_str(),
-                )
+            query
                 .execute(&mut *tx)
-                .await?;
-            }
-            tx.commit().await
-        });
-        futures::future::join_all(futs)
+                .await
+                .map_err(DatabaseAdapterError::failed_query)?;
+        }
+        tx.commit()
             .await
-            .into_iter()
-            .collect::<Result<Vec<()>, sqlx::Error>>()
-            .map_err(|e| DatabaseAdapterError {
-                kind: DatabaseAdapterErrorKind::QueryFailed(e),
-            })?;
-
-        Ok(())
+            .map_err(DatabaseAdapterError::failed_query)?;
+        sqlx::query(drop_query.as_str())
+            .execute(&mut *conn)
+            .await
+            .map_err(DatabaseAdapterError::failed_query)
+            .map(|_| ())
     }
-}
 
-#[async_trait]
-impl DatabaseAdapter for MySQLAdapter {
-    async fn apply_mask(&self, masker: &Masker) -> Result<(), DatabaseAdapterError> {
-        let pool = sqlx::mysql::MySqlPoolOptions::new()
-            .max_connections(5)
-            .connect(self.connection_creds.get_as_string().as_str())
-            .await
-            .map_err(DatabaseAdapterError::connection_error)?;
-        self.verify_entities(masker, &pool).await?;
-        let futs = masker
-            .get_entities()
+    // Every exported batch creates and drops the temporary table it needs, so that it doesn't
+    // depend on the batches of the other tables that are written around.
+    fn script_block(
+        &self,
+        masker_entity: &masker::Entity,
+        table_columns: &TableColumns,
+        statements: &[Statement],
+    ) -> Result<Vec<String>, DatabaseAdapterError> {
+        let (setup, drop_query) = self.batch_table_queries(masker_entity, table_columns)?;
+        let update = (self.prepare_batch_update_query(masker_entity), vec![]);
+        let inlined = statements
             .iter()
-            .map(|entity| self.mask_table(entity, &pool));
-        let res: Result<Vec<()>, DatabaseAdapterError> = join_all(futs).await.into_iter().collect();
-        res.map(|_| ())
+            .chain([&update])
+            .map(|(query_str, args)| script::inline_params(query_str, args, '`', literal))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(setup
+            .into_iter()
+            .chain([String::from("START TRANSACTION")])
+            .chain(inlined)
+            .chain([String::from("COMMIT"), drop_query])
+            .collect())
     }
 }
 
@@ -200,12 +526,16 @@ mod tests {
         generator::{Options, SimpleGenerator},
         FieldKind,
     };
+    use rand::rngs::StdRng;
 
     use super::*;
-    use crate::masker::{Entity, Field};
+    use crate::database::checkpoint::Checkpoint;
+    use crate::database::error::DatabaseAdapterErrorKind;
+    use crate::database::script::Output;
+    use crate::masker::{Entity, Field, Masker, PkColumn, PkType};
 
     fn get_generator() -> Box<SimpleGenerator> {
-        Box::new(SimpleGenerator::new(|_: &Options| {
+        Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
             Ok(GeneratedValue::String(FirstName(EN).fake::<String>()))
         }))
     }
@@ -227,11 +557,12 @@ mod tests {
                 "db_name".to_string(),
                 "port".to_string(),
             ),
+            scheduler: Scheduler::new(5, 1000, 5),
         }
     }
 
     #[test]
-    fn adapter_generates_updata_query_from_masker_entity_with_int_pk() {
+    fn adapter_generates_batch_insert_query_from_masker_entity_with_int_pk() {
         let adapter = get_adapter();
         let t_name = "table";
         let pk_name = "id";
@@ -243,18 +574,31 @@ mod tests {
                 get_generator(),
             ),
         ];
-        let entity = Entity::new(t_name.to_string(), pk_name.to_string(), PkType::Int, fields);
-        let id = 123;
-        let query = adapter.prepare_entity_query(&entity, Box::new(id)).unwrap();
-        let r = regex::Regex::new(
-            r"UPDATE [A-z0-9_]+ SET [A-z_]+ = '[A-z]+', [A-z_]+ = '[A-z]+' WHERE [a-z_]+ = \d+",
-        )
-        .unwrap();
-        assert!(r.is_match(query.as_str()))
+        let entity = Entity::new(
+            t_name.to_string(),
+            vec![PkColumn::new(pk_name.to_string(), PkType::Int)],
+            fields,
+        );
+        let rows = vec![
+            (vec![GeneratedValue::Number("123".to_string())], vec![]),
+            (vec![GeneratedValue::Number("124".to_string())], vec![]),
+        ];
+        let (query, args) = adapter
+            .prepare_batch_query(&entity, &TableColumns::new(), &rows)
+            .unwrap();
+        assert_eq!(
+            query,
+            "INSERT INTO `_masker_batch` (`id`, `name`, `last_name`) VALUES (?, ?, ?), (?, ?, ?)"
+        );
+        assert_eq!(args.len(), 6);
+        assert_eq!(args[0], rows[0].0[0]);
+        assert!(matches!(args[1], GeneratedValue::String(_)));
+        assert!(matches!(args[2], GeneratedValue::String(_)));
+        assert_eq!(args[3], rows[1].0[0]);
     }
 
     #[test]
-    fn adapter_generates_updata_query_from_masker_entity_with_string_pk() {
+    fn adapter_generates_batch_insert_query_from_masker_entity_with_string_pk() {
         let adapter = get_adapter();
         let t_name = "table";
         let pk_name = "id";
@@ -268,17 +612,191 @@ mod tests {
         ];
         let entity = Entity::new(
             t_name.to_string(),
-            pk_name.to_string(),
-            PkType::String,
+            vec![PkColumn::new(pk_name.to_string(), PkType::String)],
             fields,
         );
-        let id = 123;
-        let query = adapter.prepare_entity_query(&entity, Box::new(id)).unwrap();
-        let r = regex::Regex::new(
-            r"UPDATE [A-z0-9_]+ SET [A-z_]+ = '[A-z]+', [A-z_]+ = '[A-z]+' WHERE [a-z_]+ = '\w+'",
-        )
-        .unwrap();
-        assert!(r.is_match(query.as_str()))
+        let id = GeneratedValue::String("123".to_string());
+        let (query, args) = adapter
+            .prepare_batch_query(&entity, &TableColumns::new(), &[(vec![id.clone()], vec![])])
+            .unwrap();
+        assert_eq!(
+            query,
+            "INSERT INTO `_masker_batch` (`id`, `name`, `last_name`) VALUES (?, ?, ?)"
+        );
+        assert_eq!(args[0], id);
+    }
+
+    #[test]
+    fn adapter_generates_batch_queries_from_masker_entity_with_composite_pk() {
+        let adapter = get_adapter();
+        let fields: Vec<Field> = vec![Field::new(
+            "name".to_string(),
+            FieldKind::FirstName,
+            get_generator(),
+        )];
+        let entity = Entity::new(
+            "payments".to_string(),
+            vec![
+                PkColumn::new("customerNumber".to_string(), PkType::Int),
+                PkColumn::new("checkNumber".to_string(), PkType::String),
+            ],
+            fields,
+        );
+        let key = vec![
+            GeneratedValue::Number("103".to_string()),
+            GeneratedValue::String("HQ336336".to_string()),
+        ];
+        let columns = TableColumns::from([
+            (
+                "customerNumber".to_string(),
+                ColumnInfo::new("int".to_string(), None, Charset::Latin1),
+            ),
+            (
+                "checkNumber".to_string(),
+                ColumnInfo::new("varchar(50)".to_string(), Some(50), Charset::Utf8)
+                    .with_collation(Some("utf8mb4_0900_ai_ci".to_string())),
+            ),
+            (
+                "name".to_string(),
+                ColumnInfo::new("varchar(50)".to_string(), Some(50), Charset::Utf8)
+                    .with_collation(Some("utf8mb4_bin".to_string())),
+            ),
+        ]);
+        assert_eq!(
+            adapter.prepare_batch_table_query(&entity, &columns).unwrap(),
+            "CREATE TEMPORARY TABLE `_masker_batch` (`customerNumber` int NOT NULL, \
+            `checkNumber` varchar(50) COLLATE `utf8mb4_0900_ai_ci` NOT NULL, \
+            `name` varchar(50) COLLATE `utf8mb4_bin`, PRIMARY KEY (`customerNumber`, `checkNumber`))"
+        );
+        assert!(matches!(
+            adapter
+                .prepare_batch_table_query(&entity, &TableColumns::new())
+                .unwrap_err()
+                .kind,
+            DatabaseAdapterErrorKind::InconsistentSchema(_)
+        ));
+        assert_eq!(
+            adapter.prepare_batch_update_query(&entity),
+            "UPDATE `payments` AS t JOIN `_masker_batch` AS b ON t.`customerNumber` = b.`customerNumber` AND t.`checkNumber` = b.`checkNumber` SET t.`name` = b.`name`"
+        );
+        let (_, args) = adapter
+            .prepare_batch_query(&entity, &TableColumns::new(), &[(key.clone(), vec![])])
+            .unwrap();
+        assert_eq!(args[..2], key);
+    }
+
+    #[test]
+    fn adapter_never_puts_generated_values_into_query() {
+        let adapter = get_adapter();
+        let fields: Vec<Field> = vec![Field::new(
+            "name".to_string(),
+            FieldKind::Template,
+            Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                Ok(GeneratedValue::String(r#"O'Neil "Jr" \"#.to_string()))
+            })),
+        )];
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            fields,
+        );
+        let key = vec![GeneratedValue::Number("1".to_string())];
+        let (query, args) = adapter
+            .prepare_batch_query(&entity, &TableColumns::new(), &[(key, vec![])])
+            .unwrap();
+        assert_eq!(
+            query,
+            "INSERT INTO `_masker_batch` (`id`, `name`) VALUES (?, ?)"
+        );
+        assert_eq!(
+            args[1],
+            GeneratedValue::String(r#"O'Neil "Jr" \"#.to_string())
+        );
+    }
+
+    #[test]
+    fn adapter_quotes_reserved_and_unusual_identifiers() {
+        let adapter = get_adapter();
+        let fields: Vec<Field> = vec![
+            Field::new("order".to_string(), FieldKind::FirstName, get_generator()),
+            Field::new(
+                "Group Name".to_string(),
+                FieldKind::FirstName,
+                get_generator(),
+            ),
+            Field::new("we`ird".to_string(), FieldKind::FirstName, get_generator()),
+        ];
+        let entity = Entity::new(
+            "shop.Key".to_string(),
+            vec![PkColumn::new("key".to_string(), PkType::Int)],
+            fields,
+        );
+        assert_eq!(
+            adapter.prepare_batch_update_query(&entity),
+            "UPDATE `shop`.`Key` AS t JOIN `_masker_batch` AS b ON t.`key` = b.`key` SET t.`order` = b.`order`, t.`Group Name` = b.`Group Name`, t.`we``ird` = b.`we``ird`"
+        );
+    }
+
+    #[test]
+    fn adapter_exports_values_as_literals_safe_for_any_sql_mode() {
+        assert_eq!(literal(&GeneratedValue::Number("42".to_string())), "42");
+        assert_eq!(
+            literal(&GeneratedValue::Number("42; DROP TABLE t".to_string())),
+            "'42; DROP TABLE t'"
+        );
+        assert_eq!(
+            literal(&GeneratedValue::String("O'Neil".to_string())),
+            "'O''Neil'"
+        );
+        assert_eq!(
+            literal(&GeneratedValue::String(r"a\'".to_string())),
+            "X'615C27'"
+        );
+        assert_eq!(literal(&GeneratedValue::Bytes(vec![1, 255])), "X'01FF'");
+    }
+
+    #[test]
+    fn adapter_splits_batch_to_fit_placeholder_limit() {
+        let fields: Vec<Field> = (0..9)
+            .map(|i| Field::new(format!("col{i}"), FieldKind::FirstName, get_generator()))
+            .collect();
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            fields,
+        );
+        assert_eq!(
+            shared::rows_per_statement(&entity, MySQLAdapter::MAX_PARAMS),
+            6553
+        );
+    }
+
+    #[test]
+    fn adapter_rejects_identifiers_it_cannot_quote() {
+        for name in [
+            "",
+            "trailing ",
+            "nul\0char",
+            "emoji_\u{1F600}",
+            &"a".repeat(65),
+        ] {
+            let masker = Masker::new(vec![Entity::new(
+                "customers".to_string(),
+                vec![PkColumn::new("id".to_string(), PkType::Int)],
+                vec![Field::new(
+                    name.to_string(),
+                    FieldKind::FirstName,
+                    get_generator(),
+                )],
+            )]);
+            let err = shared::validate_identifiers(&masker, MySQLAdapter::is_valid_identifier)
+                .unwrap_err();
+            assert!(
+                matches!(err.kind, DatabaseAdapterErrorKind::InvalidIdentifier(_)),
+                "{name:?}"
+            );
+        }
+        assert!(MySQLAdapter::is_valid_identifier(&"a".repeat(64)));
     }
 
     #[test]
@@ -289,12 +807,13 @@ mod tests {
         let fields: Vec<Field> = vec![];
         let entity = Entity::new(
             t_name.to_string(),
-            pk_name.to_string(),
-            PkType::String,
+            vec![PkColumn::new(pk_name.to_string(), PkType::String)],
             fields,
         );
-        let id = 123;
-        assert!(adapter.prepare_entity_query(&entity, Box::new(id)).is_err());
+        let id = GeneratedValue::String("123".to_string());
+        assert!(adapter
+            .prepare_batch_query(&entity, &TableColumns::new(), &[(vec![id], vec![])])
+            .is_err());
     }
 
     #[tokio::test]
@@ -308,7 +827,11 @@ mod tests {
             FieldKind::FirstName,
             get_generator(),
         )];
-        let entity = Entity::new(t_name.to_string(), pk_name.to_string(), PkType::Int, fields);
+        let entity = Entity::new(
+            t_name.to_string(),
+            vec![PkColumn::new(pk_name.to_string(), PkType::Int)],
+            fields,
+        );
         let pool = get_test_conn().await;
         let before = sqlx::query(
             format!(
@@ -321,7 +844,15 @@ mod tests {
         .await
         .unwrap()
         .get::<String, _>(0);
-        adapter.mask_table(&entity, &pool).await.unwrap();
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
         let after = sqlx::query(
             format!(
                 "SELECT {} FROM {} ORDER BY {} ASC LIMIT 1",
@@ -343,12 +874,44 @@ mod tests {
         let t_name = "customers";
         let pk_name = "customerNumber";
         let fields: Vec<Field> = vec![];
-        let entity = Entity::new(t_name.to_string(), pk_name.to_string(), PkType::Int, fields);
+        let entity = Entity::new(
+            t_name.to_string(),
+            vec![PkColumn::new(pk_name.to_string(), PkType::Int)],
+            fields,
+        );
+        let pool = get_test_conn().await;
+
+        let expected: Vec<BatchRow> = ["103", "112", "114", "119", "121"]
+            .iter()
+            .map(|id| (vec![GeneratedValue::Number(id.to_string())], vec![]))
+            .collect();
+        let res = adapter
+            .get_batch_to_update(&entity, &pool, &TableColumns::new(), 5, None)
+            .await
+            .unwrap();
+
+        assert_eq!(res, expected)
+    }
+
+    #[tokio::test]
+    async fn adapter_get_batch_to_update_returns_ids_after_last_key() {
+        let adapter = get_adapter();
+        let t_name = "customers";
+        let pk_name = "customerNumber";
+        let entity = Entity::new(
+            t_name.to_string(),
+            vec![PkColumn::new(pk_name.to_string(), PkType::Int)],
+            vec![],
+        );
         let pool = get_test_conn().await;
 
-        let expected = vec!["103", "112", "114", "119", "121"];
+        let expected: Vec<BatchRow> = ["119", "121"]
+            .iter()
+            .map(|id| (vec![GeneratedValue::Number(id.to_string())], vec![]))
+            .collect();
+        let last_key = [GeneratedValue::Number("114".to_string())];
         let res = adapter
-            .get_batch_to_update(&entity, &pool, 5, 0)
+            .get_batch_to_update(&entity, &pool, &TableColumns::new(), 2, Some(&last_key))
             .await
             .unwrap();
 
@@ -0,0 +1,4 @@
+mod credentials;
+mod postgres_adapter;
+
+pub use postgres_adapter::PostgresAdapter;
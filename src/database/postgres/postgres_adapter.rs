@@ -0,0 +1,653 @@
+use super::credentials::PostgresConnectionCredentials;
+use async_trait::async_trait;
+use sqlx::Row;
+
+use crate::database::discovery::TableCatalog;
+use crate::database::error::DatabaseAdapterError;
+use crate::database::scheduler::Scheduler;
+use crate::database::schema::{Charset, ColumnInfo, TableColumns};
+use crate::database::script::{self, Statement};
+use crate::database::shared::{self, quote_identifier, quote_table_name, BatchRow, Dialect};
+use crate::masker;
+use crate::masker::error::ConfigParseError;
+use crate::masker::generator::{encode_hex, GeneratedValue};
+
+// All parameters are bound as text and then cast to the actual column type on the DB side, since
+// Postgres doesn't convert text parameters to other types implicitly. NULL is bound as NULL text.
+fn value_as_text(val: &GeneratedValue) -> Option<String> {
+    match val {
+        GeneratedValue::Number(n) => Some(n.clone()),
+        GeneratedValue::String(s) => Some(s.clone()),
+        GeneratedValue::Bytes(b) => Some(format!("\\x{}", encode_hex(b))),
+        GeneratedValue::Null => None,
+    }
+}
+
+fn get_column_type<'a>(
+    columns: &'a TableColumns,
+    t_name: &str,
+    column: &str,
+) -> Result<&'a String, DatabaseAdapterError> {
+    columns
+        .get(column)
+        .map(|c| c.get_sql_type())
+        .ok_or_else(|| DatabaseAdapterError::inconsistent_schema(format!("{t_name}.{column}")))
+}
+
+// Every text column of Postgres DB uses the encoding of the DB. SQL_ASCII stores the bytes as is,
+// while for the single byte encodings other than LATIN1 only ASCII is known to be safe.
+fn charset_from_encoding(encoding: &str) -> Charset {
+    match encoding {
+        "UTF8" | "SQL_ASCII" => Charset::Utf8,
+        "LATIN1" => Charset::Latin1,
+        _ => Charset::Ascii,
+    }
+}
+
+// Every parameter is cast to the column type in the query, so in the exported script all of them
+// are written as text literals the same way they are bound.
+fn literal(val: &GeneratedValue) -> String {
+    match value_as_text(val) {
+        Some(s) => script::quote_string_literal(&s),
+        None => String::from("NULL"),
+    }
+}
+
+pub struct PostgresAdapter {
+    connection_creds: PostgresConnectionCredentials,
+    scheduler: Scheduler,
+}
+
+impl PostgresAdapter {
+    pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
+        let connection_creds = PostgresConnectionCredentials::from_yaml(yaml)?;
+        let scheduler = Scheduler::new_from_yaml(yaml, 5)?;
+        Ok(PostgresAdapter {
+            connection_creds,
+            scheduler,
+        })
+    }
+}
+
+#[async_trait]
+impl Dialect for PostgresAdapter {
+    type Pool = sqlx::PgPool;
+    // Postgres protocol limits the number of parameters of a single statement
+    const MAX_PARAMS: usize = 65535;
+
+    fn get_scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    // Postgres silently truncates identifiers longer than 63 bytes, which would make masker touch
+    // a different column than the one specified in config.
+    fn is_valid_identifier(ident: &str) -> bool {
+        !ident.is_empty() && ident.len() <= 63 && !ident.contains('\0')
+    }
+
+    async fn connect(&self, read_only: bool) -> Result<Self::Pool, DatabaseAdapterError> {
+        sqlx::postgres::PgPoolOptions::new()
+            .max_connections(self.scheduler.get_pool_size())
+            .after_connect(move |conn, _| {
+                Box::pin(async move {
+                    if read_only {
+                        sqlx::query("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY")
+                            .execute(conn)
+                            .await?;
+                    }
+                    Ok(())
+                })
+            })
+            .connect(self.connection_creds.get_as_string().as_str())
+            .await
+            .map_err(DatabaseAdapterError::connection_error)
+    }
+
+    // Reads the actual types of the table columns from the catalog. Parameters are sent to
+    // Postgres as text, so they have to be cast explicitly to be compared with or assigned to
+    // non-text columns. Type modifiers are omitted on purpose, since explicit cast to e.g.
+    // varchar(20) would silently truncate the value. The length limit of varchar and char
+    // columns is read separately instead, so that generated values could be fitted to it, along
+    // with the position of the column in the primary key. Table is looked up by its name, so
+    // that the missing one has no columns rather than failing the query.
+    async fn get_columns(
+        &self,
+        masker_entity: &masker::Entity,
+        p: &Self::Pool,
+    ) -> Result<TableColumns, sqlx::Error> {
+        let t_name = masker_entity.get_table_name();
+        let (schema, table) = shared::split_table_name(&t_name);
+        sqlx::query(
+            "SELECT a.attname::text, format_type(a.atttypid, NULL), \
+            CASE WHEN a.atttypid IN ('varchar'::regtype, 'bpchar'::regtype) AND a.atttypmod > 0 \
+            THEN a.atttypmod - 4 END, getdatabaseencoding()::text, \
+            (SELECT k.n FROM pg_index AS i, unnest(i.indkey) WITH ORDINALITY AS k(attnum, n) \
+            WHERE i.indrelid = a.attrelid AND i.indisprimary AND k.attnum = a.attnum) \
+            FROM pg_attribute AS a JOIN pg_class AS c ON c.oid = a.attrelid \
+            JOIN pg_namespace AS n ON n.oid = c.relnamespace \
+            WHERE n.nspname = COALESCE($1, current_schema()::text) AND c.relname = $2 \
+            AND a.attnum > 0 AND NOT a.attisdropped;",
+        )
+        .bind(schema)
+        .bind(table)
+        .fetch_all(p)
+        .await?
+        .iter()
+        .map(|r| {
+            let max_length = r.try_get::<Option<i32>, _>(2)?.map(|l| l as usize);
+            let charset = charset_from_encoding(&r.try_get::<String, _>(3)?);
+            let pk_position = r.try_get::<Option<i64>, _>(4)?.map(|p| p as usize);
+            let column = ColumnInfo::new(r.try_get::<String, _>(1)?, max_length, charset)
+                .with_pk_position(pk_position);
+            Ok((r.try_get::<String, _>(0)?, column))
+        })
+        .collect()
+    }
+
+    async fn get_total_size(
+        &self,
+        masker_entity: &masker::Entity,
+        p: &Self::Pool,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query(shared::count_query(masker_entity, quote_table_name).as_str())
+            .fetch_one(p)
+            .await?
+            .try_get(0)
+    }
+
+    async fn get_batch_to_update(
+        &self,
+        masker_entity: &masker::Entity,
+        p: &Self::Pool,
+        table_columns: &TableColumns,
+        b_size: i64,
+        last_key: Option<&[GeneratedValue]>,
+    ) -> Result<Vec<BatchRow>, sqlx::Error> {
+        let pk = masker_entity.get_pk();
+        // Postgres folds unquoted identifiers to lower case, so all of them are quoted and thus
+        // have to be specified in config exactly as they are stored in the catalog.
+        let pk_names = pk
+            .iter()
+            .map(|c| quote_identifier(c.get_name()))
+            .collect::<Vec<String>>();
+        let key_types = pk
+            .iter()
+            .map(|c| {
+                table_columns
+                    .get(c.get_name())
+                    .map(|column| column.get_sql_type())
+                    .ok_or_else(|| sqlx::Error::ColumnNotFound(c.get_name().clone()))
+            })
+            .collect::<Result<Vec<&String>, sqlx::Error>>()?;
+        let conds = match last_key {
+            Some(_) => vec![format!(
+                "({}) > ({})",
+                pk_names.join(", "),
+                key_types
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| format!("CAST(${} AS {})", i + 1, t))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )],
+            None => vec![],
+        };
+        // Values are cast to text on the DB side, so that they can be decoded regardless of the
+        // actual column type (int4, int8, varchar, uuid...)
+        let query_str = format!(
+            "SELECT {} FROM {} {}ORDER BY {} LIMIT ${}",
+            pk_names
+                .iter()
+                .cloned()
+                .chain(
+                    masker_entity
+                        .get_entries()
+                        .iter()
+                        .map(|f| quote_identifier(f.get_column_name()))
+                )
+                .map(|c| format!("{}::text", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            quote_table_name(&masker_entity.get_table_name()),
+            shared::where_clause(masker_entity, &conds),
+            pk_names.join(", "),
+            last_key.map_or(0, |key| key.len()) + 1
+        );
+        let mut query = sqlx::query(query_str.as_str());
+        for val in last_key.unwrap_or_default() {
+            query = query.bind(value_as_text(val));
+        }
+        query
+            .bind(b_size)
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| {
+                let key = pk
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| Ok(shared::key_value_from_str(c, r.try_get::<String, _>(i)?)))
+                    .collect::<Result<Vec<GeneratedValue>, sqlx::Error>>()?;
+                let originals = (pk.len()..r.len())
+                    .map(|i| r.try_get::<Option<String>, _>(i))
+                    .collect::<Result<Vec<Option<String>>, sqlx::Error>>()?;
+                Ok((key, originals))
+            })
+            .collect()
+    }
+
+    // Describes every table of the DB for discovery, along with the position of every column in
+    // the primary key. Tables outside of the current schema are qualified with their schema.
+    async fn get_catalog(&self, p: &Self::Pool) -> Result<Vec<TableCatalog>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT CASE WHEN c.table_schema = current_schema() THEN c.table_name::text \
+            ELSE c.table_schema || '.' || c.table_name END, c.column_name::text, \
+            c.data_type::text, COALESCE(k.ordinal_position, 0)::int8 \
+            FROM information_schema.columns AS c \
+            JOIN information_schema.tables AS t ON t.table_schema = c.table_schema \
+            AND t.table_name = c.table_name AND t.table_type = 'BASE TABLE' \
+            LEFT JOIN information_schema.table_constraints AS tc \
+            ON tc.table_schema = c.table_schema AND tc.table_name = c.table_name \
+            AND tc.constraint_type = 'PRIMARY KEY' \
+            LEFT JOIN information_schema.key_column_usage AS k \
+            ON k.constraint_schema = tc.constraint_schema \
+            AND k.constraint_name = tc.constraint_name AND k.column_name = c.column_name \
+            WHERE c.table_schema NOT IN ('pg_catalog', 'information_schema') \
+            ORDER BY c.table_schema, c.table_name, c.ordinal_position;",
+        )
+        .fetch_all(p)
+        .await?
+        .iter()
+        .map(|r| Ok((r.try_get(0)?, r.try_get(1)?, r.try_get(2)?, r.try_get(3)?)))
+        .collect::<Result<Vec<(String, String, String, i64)>, sqlx::Error>>()?;
+        Ok(TableCatalog::from_rows(rows))
+    }
+
+    async fn get_column_values(
+        &self,
+        masker_entity: &masker::Entity,
+        column: &str,
+        p: &Self::Pool,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let column = quote_identifier(column);
+        let query_str = format!(
+            "SELECT DISTINCT {}::text FROM {} WHERE {} IS NOT NULL",
+            column,
+            quote_table_name(&masker_entity.get_table_name()),
+            column
+        );
+        sqlx::query(query_str.as_str())
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| r.try_get::<String, _>(0))
+            .collect()
+    }
+
+    async fn get_column_samples(
+        &self,
+        catalog: &TableCatalog,
+        p: &Self::Pool,
+        limit: i64,
+    ) -> Result<Vec<Vec<Option<String>>>, sqlx::Error> {
+        let columns = catalog.get_text_columns();
+        if columns.is_empty() {
+            return Ok(vec![]);
+        }
+        let query_str = format!(
+            "SELECT {} FROM {} LIMIT $1",
+            columns
+                .iter()
+                .map(|c| format!("{}::text", quote_identifier(c)))
+                .collect::<Vec<String>>()
+                .join(", "),
+            quote_table_name(catalog.get_table_name())
+        );
+        sqlx::query(query_str.as_str())
+            .bind(limit)
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| {
+                (0..r.len())
+                    .map(|i| r.try_get::<Option<String>, _>(i))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Every value of the VALUES list is cast to the type of its column, since the list itself
+    // has no column types to convert the text parameters to
+    fn prepare_batch_query(
+        &self,
+        masker_entity: &masker::Entity,
+        table_columns: &TableColumns,
+        batch: &[BatchRow],
+    ) -> Result<Statement, DatabaseAdapterError> {
+        let args = shared::generate_batch_values(masker_entity, table_columns, batch)?;
+        let t_name = masker_entity.get_table_name();
+        let columns = shared::batch_columns(masker_entity);
+        let flags = shared::keep_flag_columns(masker_entity);
+        let casts = columns
+            .iter()
+            .map(|c| match flags.contains(c) {
+                true => Ok("boolean"),
+                false => get_column_type(table_columns, &t_name, c).map(|t| t.as_str()),
+            })
+            .collect::<Result<Vec<&str>, DatabaseAdapterError>>()?;
+        let rows = (0..batch.len())
+            .map(|row| {
+                let values = casts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| format!("CAST(${} AS {})", row * casts.len() + i + 1, t))
+                    .collect::<Vec<String>>();
+                format!("({})", values.join(", "))
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        let set = masker_entity
+            .get_entries()
+            .iter()
+            .map(|f| {
+                let column = quote_identifier(f.get_column_name());
+                if f.has_keep_rules() {
+                    let flag = quote_identifier(&shared::keep_flag_column(f.get_column_name()));
+                    format!("{column} = CASE WHEN v.{flag} THEN t.{column} ELSE v.{column} END")
+                } else {
+                    format!("{} = v.{}", column, column)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        let cond = masker_entity
+            .get_pk()
+            .iter()
+            .map(|c| {
+                let column = quote_identifier(c.get_name());
+                format!("t.{} = v.{}", column, column)
+            })
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let query = format!(
+            "UPDATE {} AS t SET {} FROM (VALUES {}) AS v ({}) WHERE {}",
+            quote_table_name(&t_name),
+            set,
+            rows,
+            columns
+                .iter()
+                .map(|c| quote_identifier(c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            cond
+        );
+
+        Ok((query, args))
+    }
+
+    async fn write_batch(
+        &self,
+        _: &masker::Entity,
+        p: &Self::Pool,
+        _: &TableColumns,
+        statements: &[Statement],
+    ) -> Result<(), DatabaseAdapterError> {
+        let mut tx = p
+            .begin()
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        for (query_str, args) in statements.iter() {
+            let mut query = sqlx::query(query_str.as_str());
+            for val in args.iter() {
+                query = query.bind(value_as_text(val));
+            }
+            query
+                .execute(&mut *tx)
+                .await
+                .map_err(DatabaseAdapterError::failed_query)?;
+        }
+        tx.commit()
+            .await
+            .map_err(DatabaseAdapterError::failed_query)
+    }
+
+    fn script_block(
+        &self,
+        _: &masker::Entity,
+        _: &TableColumns,
+        statements: &[Statement],
+    ) -> Result<Vec<String>, DatabaseAdapterError> {
+        let inlined = statements
+            .iter()
+            .map(|(query_str, args)| script::inline_params(query_str, args, '"', literal))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok([String::from("BEGIN")]
+            .into_iter()
+            .chain(inlined)
+            .chain([String::from("COMMIT")])
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{faker::name::raw::*, locales::EN, Fake};
+    use masker::{
+        generator::{Options, SimpleGenerator},
+        FieldKind,
+    };
+    use rand::rngs::StdRng;
+
+    use super::*;
+    use crate::database::error::DatabaseAdapterErrorKind;
+    use crate::masker::{Entity, Field, OverflowPolicy, PkColumn, PkType};
+
+    fn get_generator() -> Box<SimpleGenerator> {
+        Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+            Ok(GeneratedValue::String(FirstName(EN).fake::<String>()))
+        }))
+    }
+
+    fn get_adapter() -> PostgresAdapter {
+        PostgresAdapter {
+            connection_creds: PostgresConnectionCredentials::new(
+                "test".to_string(),
+                "username".to_string(),
+                "password".to_string(),
+                "db_name".to_string(),
+                "port".to_string(),
+            ),
+            scheduler: Scheduler::new(5, 1000, 5),
+        }
+    }
+
+    fn get_fields() -> Vec<Field> {
+        vec![
+            Field::new("name".to_string(), FieldKind::FirstName, get_generator()),
+            Field::new(
+                "last_name".to_string(),
+                FieldKind::LastName,
+                get_generator(),
+            ),
+        ]
+    }
+
+    fn get_column(sql_type: &str) -> ColumnInfo {
+        ColumnInfo::new(sql_type.to_string(), None, Charset::Utf8)
+    }
+
+    fn get_column_types() -> TableColumns {
+        TableColumns::from([
+            ("id".to_string(), get_column("integer")),
+            ("name".to_string(), get_column("character varying")),
+            ("last_name".to_string(), get_column("text")),
+        ])
+    }
+
+    #[test]
+    fn adapter_generates_batch_query_from_masker_entity_with_int_pk() {
+        let adapter = get_adapter();
+        let entity = Entity::new(
+            "table".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            get_fields(),
+        );
+        let rows = vec![
+            (vec![GeneratedValue::Number("123".to_string())], vec![]),
+            (vec![GeneratedValue::Number("124".to_string())], vec![]),
+        ];
+        let (query, args) = adapter
+            .prepare_batch_query(&entity, &get_column_types(), &rows)
+            .unwrap();
+        assert_eq!(
+            query,
+            r#"UPDATE "table" AS t SET "name" = v."name", "last_name" = v."last_name" FROM (VALUES (CAST($1 AS integer), CAST($2 AS character varying), CAST($3 AS text)), (CAST($4 AS integer), CAST($5 AS character varying), CAST($6 AS text))) AS v ("id", "name", "last_name") WHERE t."id" = v."id""#
+        );
+        assert_eq!(args.len(), 6);
+        assert_eq!(args[0], rows[0].0[0]);
+        assert_eq!(args[3], rows[1].0[0]);
+    }
+
+    #[test]
+    fn adapter_quotes_schema_qualified_table_name() {
+        let adapter = get_adapter();
+        let entity = Entity::new(
+            "public.users".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            get_fields(),
+        );
+        let (query, _) = adapter
+            .prepare_batch_query(
+                &entity,
+                &get_column_types(),
+                &[(vec![GeneratedValue::Number("1".to_string())], vec![])],
+            )
+            .unwrap();
+        assert!(
+            query.starts_with(r#"UPDATE "public"."users" AS t SET "#),
+            "{query}"
+        )
+    }
+
+    #[test]
+    fn adapter_escapes_quotes_in_identifiers() {
+        assert_eq!(quote_identifier(r#"we"ird"#), r#""we""ird""#);
+        assert_eq!(quote_table_name("public.users"), r#""public"."users""#);
+    }
+
+    #[test]
+    fn adapter_rejects_identifiers_postgres_would_truncate() {
+        assert!(PostgresAdapter::is_valid_identifier(&"a".repeat(63)));
+        assert!(!PostgresAdapter::is_valid_identifier(&"a".repeat(64)));
+        // Length limit is in bytes, not in characters.
+        assert!(!PostgresAdapter::is_valid_identifier(&"ä".repeat(32)));
+        assert!(!PostgresAdapter::is_valid_identifier(""));
+    }
+
+    #[test]
+    fn adapter_fails_when_column_is_missing_in_db() {
+        let adapter = get_adapter();
+        let entity = Entity::new(
+            "table".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            get_fields(),
+        );
+        let mut column_types = get_column_types();
+        column_types.remove("last_name");
+        let err = adapter
+            .prepare_batch_query(
+                &entity,
+                &column_types,
+                &[(vec![GeneratedValue::Number("1".to_string())], vec![])],
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::InconsistentSchema(c) if c == "table.last_name"
+        ))
+    }
+
+    #[test]
+    fn adapter_fits_generated_values_to_column_length() {
+        let adapter = get_adapter();
+        let get_entity = |on_overflow| {
+            Entity::new(
+                "table".to_string(),
+                vec![PkColumn::new("id".to_string(), PkType::Int)],
+                vec![Field::new(
+                    "name".to_string(),
+                    FieldKind::FirstName,
+                    Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                        Ok(GeneratedValue::String("Christopher".to_string()))
+                    })),
+                )
+                .with_overflow_policy(on_overflow)],
+            )
+        };
+        let mut column_types = get_column_types();
+        column_types.insert(
+            "name".to_string(),
+            ColumnInfo::new("character varying".to_string(), Some(5), Charset::Utf8),
+        );
+        let key = [(vec![GeneratedValue::Number("1".to_string())], vec![])];
+
+        let (_, args) = adapter
+            .prepare_batch_query(&get_entity(OverflowPolicy::Truncate), &column_types, &key)
+            .unwrap();
+        assert_eq!(args[1], GeneratedValue::String("Chris".to_string()));
+        let err = adapter
+            .prepare_batch_query(&get_entity(OverflowPolicy::Reject), &column_types, &key)
+            .unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::ValueDoesntFitColumn(c, _) if c == "table.name"
+        ))
+    }
+
+    #[test]
+    fn adapter_throws_error_if_entity_has_no_fields() {
+        let adapter = get_adapter();
+        let entity = Entity::new(
+            "table".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::String)],
+            vec![],
+        );
+        assert!(adapter
+            .prepare_batch_query(
+                &entity,
+                &get_column_types(),
+                &[(vec![GeneratedValue::String("123".to_string())], vec![])]
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn adapter_matches_every_column_of_composite_pk() {
+        let adapter = get_adapter();
+        let entity = Entity::new(
+            "orderdetails".to_string(),
+            vec![
+                PkColumn::new("orderNumber".to_string(), PkType::Int),
+                PkColumn::new("productCode".to_string(), PkType::String),
+            ],
+            get_fields(),
+        );
+        let mut column_types = get_column_types();
+        column_types.insert("orderNumber".to_string(), get_column("integer"));
+        column_types.insert("productCode".to_string(), get_column("character varying"));
+        let key = vec![
+            GeneratedValue::Number("10100".to_string()),
+            GeneratedValue::String("S18_1749".to_string()),
+        ];
+        let (query, args) = adapter
+            .prepare_batch_query(&entity, &column_types, &[(key.clone(), vec![])])
+            .unwrap();
+        assert!(
+            query.ends_with(
+                r#" AS v ("orderNumber", "productCode", "name", "last_name") WHERE t."orderNumber" = v."orderNumber" AND t."productCode" = v."productCode""#
+            ),
+            "{query}"
+        );
+        assert_eq!(args[..2], key);
+    }
+}
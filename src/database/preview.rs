@@ -0,0 +1,206 @@
+use std::fmt::Display;
+
+use crate::database::error::DatabaseAdapterError;
+use crate::database::schema::TableColumns;
+use crate::database::shared::{self, BatchRow};
+use crate::masker::generator::GeneratedValue;
+use crate::masker::Entity;
+
+// Number of rows of every entity that are shown before and after masking
+pub const SAMPLE_SIZE: i64 = 3;
+
+#[derive(Debug, PartialEq)]
+pub struct ColumnPreview {
+    column: String,
+    before: Option<String>,
+    after: GeneratedValue,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RowPreview {
+    key: Vec<GeneratedValue>,
+    columns: Vec<ColumnPreview>,
+}
+
+// Shows what masking would do to the entity without writing anything: how many rows would be
+// masked and how some of them would look like after masking.
+#[derive(Debug, PartialEq)]
+pub struct EntityPreview {
+    table: String,
+    total_rows: i64,
+    rows: Vec<RowPreview>,
+}
+
+impl EntityPreview {
+    // Runs the field generators for every sample row the same way masking does, but only keeps
+    // the results to be shown.
+    pub fn new(
+        masker_entity: &Entity,
+        columns: &TableColumns,
+        total_rows: i64,
+        samples: Vec<BatchRow>,
+    ) -> Result<Self, DatabaseAdapterError> {
+        let rows = samples
+            .into_iter()
+            .map(|row| {
+                let columns = shared::generate_entity_values(masker_entity, columns, &row)?;
+                let (key, before) = row;
+                let columns = columns
+                    .into_iter()
+                    .zip(before)
+                    .map(|((column, after, _), before)| ColumnPreview {
+                        column: column.clone(),
+                        before,
+                        after,
+                    })
+                    .collect();
+                Ok(RowPreview { key, columns })
+            })
+            .collect::<Result<Vec<RowPreview>, DatabaseAdapterError>>()?;
+        Ok(Self {
+            table: masker_entity.get_table_name(),
+            total_rows,
+            rows,
+        })
+    }
+}
+
+impl Display for EntityPreview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: {} rows to mask", self.table, self.total_rows)?;
+        for row in self.rows.iter() {
+            let key = row
+                .key
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join(", ");
+            writeln!(f, "  key ({key})")?;
+            for c in row.columns.iter() {
+                let before = match &c.before {
+                    Some(v) => format!("\"{v}\""),
+                    None => String::from("NULL"),
+                };
+                writeln!(f, "    {}: {} -> {}", c.column, before, c.after)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::adapter::DatabaseAdapter;
+    use crate::database::shared::Dialect;
+    use crate::database::sqlite::test_db::get_file_adapter;
+    use crate::masker::generator::{Options, SimpleGenerator};
+    use crate::masker::{Field, FieldKind, Masker, PkColumn, PkType};
+    use rand::rngs::StdRng;
+    use sqlx::Row;
+
+    fn get_entity() -> Entity {
+        Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            vec![
+                Field::new(
+                    "first_name".to_string(),
+                    FieldKind::Template,
+                    Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                        Ok(GeneratedValue::String("John".to_string()))
+                    })),
+                ),
+                Field::new(
+                    "last_name".to_string(),
+                    FieldKind::Template,
+                    Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                        Ok(GeneratedValue::String("Doe".to_string()))
+                    })),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn preview_shows_values_before_and_after_masking() {
+        let samples = vec![(
+            vec![GeneratedValue::Number("103".to_string())],
+            vec![Some("Carine".to_string()), None],
+        )];
+        let preview =
+            EntityPreview::new(&get_entity(), &TableColumns::new(), 122, samples).unwrap();
+        assert_eq!(
+            preview.to_string(),
+            "customers: 122 rows to mask\n  key (103)\n    first_name: \"Carine\" -> \"John\"\n    last_name: NULL -> \"Doe\"\n"
+        );
+    }
+
+    #[test]
+    fn preview_of_empty_table_has_no_samples() {
+        let preview = EntityPreview::new(&get_entity(), &TableColumns::new(), 0, vec![]).unwrap();
+        assert_eq!(preview.to_string(), "customers: 0 rows to mask\n");
+    }
+
+    #[tokio::test]
+    async fn preview_never_writes_to_db() {
+        let path = std::env::temp_dir().join(format!("masker_preview_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect_with(
+                sqlx::sqlite::SqliteConnectOptions::new()
+                    .filename(&path)
+                    .create_if_missing(true),
+            )
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE customers (customerNumber INTEGER PRIMARY KEY, contactFirstName TEXT);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO customers VALUES (1, 'Carine'), (2, NULL), (3, 'Jean'), (4, 'Peter');",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+        let adapter = get_file_adapter(path.to_str().unwrap());
+        let mut masker = Masker::new(vec![Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new(
+                "contactFirstName".to_string(),
+                FieldKind::Template,
+                Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                    Ok(GeneratedValue::String("masked".to_string()))
+                })),
+            )],
+        )]);
+
+        let previews = adapter.preview_mask(&mut masker).await.unwrap();
+        assert_eq!(previews.len(), 1);
+        assert_eq!(
+            previews[0].to_string(),
+            "customers: 4 rows to mask\n  key (1)\n    contactFirstName: \"Carine\" -> \"masked\"\n  key (2)\n    contactFirstName: NULL -> \"masked\"\n  key (3)\n    contactFirstName: \"Jean\" -> \"masked\"\n"
+        );
+        let pool = adapter.connect(true).await.unwrap();
+        let masked =
+            sqlx::query("SELECT COUNT(*) FROM customers WHERE contactFirstName = 'masked'")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .get::<i64, _>(0);
+        assert_eq!(masked, 0);
+        assert!(
+            sqlx::query("UPDATE customers SET contactFirstName = 'masked'")
+                .execute(&pool)
+                .await
+                .is_err()
+        );
+        pool.close().await;
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+use futures::{stream, Future, StreamExt, TryStreamExt};
+use tokio::sync::Semaphore;
+
+use crate::database::shared;
+use crate::masker::error::{ConfigParseError, ConfigParseErrorKind};
+
+const DEFAULT_BATCH_SIZE: u64 = 1000;
+
+// Controls how much load masking puts on the DB. All of the options are read from the `db`
+// section of the config and are optional:
+//   pool_size  - max number of connections opened to the DB
+//   batch_size - number of rows fetched and written within a single transaction
+//   workers    - number of batches fetched or written at the same time across all of the tables,
+//                defaults to pool_size
+#[derive(Debug)]
+pub struct Scheduler {
+    pool_size: u32,
+    batch_size: i64,
+    workers: usize,
+    permits: Semaphore,
+}
+
+impl PartialEq for Scheduler {
+    fn eq(&self, other: &Self) -> bool {
+        self.pool_size == other.pool_size
+            && self.batch_size == other.batch_size
+            && self.workers == other.workers
+    }
+}
+
+impl Scheduler {
+    pub fn new(pool_size: u32, batch_size: i64, workers: usize) -> Self {
+        Self {
+            pool_size,
+            batch_size,
+            workers,
+            permits: Semaphore::new(workers),
+        }
+    }
+
+    pub fn new_from_yaml(
+        yaml: &serde_yaml::Value,
+        default_pool_size: u32,
+    ) -> Result<Self, ConfigParseError> {
+        let empty = serde_yaml::Mapping::new();
+        let m = yaml.as_mapping().unwrap_or(&empty);
+        let pool_size = shared::read_positive_int_field(
+            m,
+            String::from("pool_size"),
+            default_pool_size as u64,
+        )?;
+        let batch_size =
+            shared::read_positive_int_field(m, String::from("batch_size"), DEFAULT_BATCH_SIZE)?;
+        let workers = shared::read_positive_int_field(m, String::from("workers"), pool_size)?;
+        // Every worker keeps a connection busy, so the ones that don't get a connection would
+        // only wait for it and eventually time out.
+        if workers > pool_size {
+            return Err(ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(format!(
+                    "{workers} (can't be greater than pool_size {pool_size})"
+                )),
+                field: String::from("workers"),
+            });
+        }
+        let out_of_range = |field: &str, val: u64| ConfigParseError {
+            kind: ConfigParseErrorKind::UnexpectedFieldValue(val.to_string()),
+            field: String::from(field),
+        };
+        Ok(Self::new(
+            u32::try_from(pool_size).map_err(|_| out_of_range("pool_size", pool_size))?,
+            i64::try_from(batch_size).map_err(|_| out_of_range("batch_size", batch_size))?,
+            workers as usize,
+        ))
+    }
+
+    pub fn get_pool_size(&self) -> u32 {
+        self.pool_size
+    }
+    pub fn get_batch_size(&self) -> i64 {
+        self.batch_size
+    }
+    pub fn get_workers(&self) -> usize {
+        self.workers
+    }
+
+    // Runs the task once one of the workers is free. Tasks of every table share the same
+    // workers, so no matter how many tables and batches are in progress, at most `workers` of
+    // them use a connection at the same time.
+    pub async fn run<F: Future>(&self, task: F) -> F::Output {
+        // Semaphore is never closed, so the permit is always acquired
+        let _permit = self.permits.acquire().await;
+        task.await
+    }
+
+    // Runs the tasks with at most `workers` of them in progress at the same time. Tasks are
+    // started lazily, so a task doesn't take any resources until a worker is free to run it.
+    // Stops at the first failed task, dropping the ones that are still in progress.
+    pub async fn run_all<I, F, T, E>(&self, tasks: I) -> Result<Vec<T>, E>
+    where
+        I: IntoIterator<Item = F>,
+        F: Future<Output = Result<T, E>>,
+    {
+        stream::iter(tasks)
+            .buffer_unordered(self.workers)
+            .try_collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<Scheduler, ConfigParseError> {
+        Scheduler::new_from_yaml(&serde_yaml::from_str(yaml).unwrap(), 5)
+    }
+
+    #[test]
+    fn options_fall_back_to_defaults() {
+        assert_eq!(parse("family: mysql").unwrap(), Scheduler::new(5, 1000, 5));
+        assert_eq!(
+            parse("family: mysql\npool_size: 10").unwrap(),
+            Scheduler::new(10, 1000, 10)
+        );
+    }
+
+    #[test]
+    fn options_are_read_from_yaml() {
+        assert_eq!(
+            parse("pool_size: 8\nbatch_size: 500\nworkers: '2'").unwrap(),
+            Scheduler::new(8, 500, 2)
+        );
+    }
+
+    #[test]
+    fn options_reject_invalid_values() {
+        for (yaml, field) in [
+            ("batch_size: 0", "batch_size"),
+            ("pool_size: -1", "pool_size"),
+            ("workers: many", "workers"),
+            ("pool_size: 2\nworkers: 3", "workers"),
+        ] {
+            let err = parse(yaml).unwrap_err();
+            assert_eq!(err.field, field);
+            assert!(matches!(
+                err.kind,
+                ConfigParseErrorKind::UnexpectedFieldValue(_)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduler_limits_number_of_concurrent_tasks() {
+        let opts = Scheduler::new(3, 1000, 3);
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let tasks = (0..20).map(|i| {
+            let (running, max_running) = (&running, &max_running);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok::<usize, ()>(i)
+            }
+        });
+        let res = opts.run_all(tasks).await.unwrap();
+        assert_eq!(res.len(), 20);
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn scheduler_stops_at_first_error() {
+        let opts = Scheduler::new(1, 1000, 1);
+        let started = AtomicUsize::new(0);
+        let tasks = (0..10).map(|i| {
+            let started = &started;
+            async move {
+                started.fetch_add(1, Ordering::SeqCst);
+                if i == 2 {
+                    Err(i)
+                } else {
+                    Ok(i)
+                }
+            }
+        });
+        assert_eq!(opts.run_all(tasks).await, Err(2));
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn scheduler_shares_workers_between_tasks() {
+        let opts = Scheduler::new(2, 1000, 2);
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let batch = || async {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+        };
+        // Every table runs its batches concurrently, but they all compete for the same workers
+        let tables = (0..3).map(|_| futures::future::join_all((0..5).map(|_| opts.run(batch()))));
+        futures::future::join_all(tables).await;
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+}
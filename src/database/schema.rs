@@ -0,0 +1,668 @@
+use std::collections::HashMap;
+
+use crate::masker::generator::GeneratedValue;
+use crate::masker::{Entity, FieldColumn, OverflowPolicy, PkColumn, PkType};
+
+// Broad class of the column type, which is enough to tell whether the values generated by masker
+// could be written to the column. Type names differ between the DBs, but are close enough for the
+// same keywords to work for all of them.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColumnClass {
+    Text,
+    Integer,
+    Decimal,
+    Binary,
+    Temporal,
+    Uuid,
+    // SQLite columns declared without a type accept values of any type
+    Untyped,
+    Other,
+}
+
+pub fn classify_column_type(sql_type: &str) -> ColumnClass {
+    // Type parameters, such as the length or the values of MySQL enum, are not part of the name
+    let mut depth = 0;
+    let sql_type: String = sql_type
+        .to_lowercase()
+        .chars()
+        .filter(|c| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => return depth == 0,
+            }
+            false
+        })
+        .collect();
+    let words: Vec<&str> = sql_type
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let has_word = |keywords: &[&str]| words.iter().any(|w| keywords.contains(w));
+    if words.is_empty() {
+        ColumnClass::Untyped
+    } else if has_word(&[
+        "date",
+        "time",
+        "datetime",
+        "timestamp",
+        "timestamptz",
+        "timetz",
+        "interval",
+        "year",
+    ]) {
+        ColumnClass::Temporal
+    } else if has_word(&["uuid", "uniqueidentifier"]) {
+        ColumnClass::Uuid
+    } else if has_word(&[
+        "char",
+        "character",
+        "varchar",
+        "nchar",
+        "nvarchar",
+        "text",
+        "tinytext",
+        "mediumtext",
+        "longtext",
+        "citext",
+        "clob",
+        "string",
+    ]) {
+        ColumnClass::Text
+    } else if has_word(&[
+        "binary",
+        "varbinary",
+        "blob",
+        "tinyblob",
+        "mediumblob",
+        "longblob",
+        "bytea",
+    ]) {
+        ColumnClass::Binary
+    } else if has_word(&[
+        "int",
+        "integer",
+        "tinyint",
+        "smallint",
+        "mediumint",
+        "bigint",
+        "int2",
+        "int4",
+        "int8",
+        "serial",
+        "smallserial",
+        "bigserial",
+    ]) {
+        ColumnClass::Integer
+    } else if has_word(&["decimal", "numeric", "real", "float", "double", "money"]) {
+        ColumnClass::Decimal
+    } else {
+        ColumnClass::Other
+    }
+}
+
+// Set of characters the column is able to store. Columns of binary types store the bytes of the
+// value as is, so their length is measured in bytes rather than in characters.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Charset {
+    Ascii,
+    Latin1,
+    // Three byte UTF-8, which only covers the basic multilingual plane (utf8mb3 of MySQL)
+    Utf8Bmp,
+    Utf8,
+    Binary,
+}
+
+impl Charset {
+    fn can_encode(&self, c: char) -> bool {
+        match self {
+            Charset::Ascii => c.is_ascii(),
+            Charset::Latin1 => (c as u32) <= 0xFF,
+            Charset::Utf8Bmp => (c as u32) <= 0xFFFF,
+            Charset::Utf8 | Charset::Binary => true,
+        }
+    }
+
+    fn len(&self, s: &str) -> usize {
+        match self {
+            Charset::Binary => s.len(),
+            _ => s.chars().count(),
+        }
+    }
+}
+
+impl std::fmt::Display for Charset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Charset::Ascii => write!(f, "ascii"),
+            Charset::Latin1 => write!(f, "latin1"),
+            Charset::Utf8Bmp => write!(f, "utf8mb3"),
+            Charset::Utf8 => write!(f, "utf8"),
+            Charset::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+// Column of the masked table as it's defined in the DB
+#[derive(Debug, PartialEq, Clone)]
+pub struct ColumnInfo {
+    sql_type: String,
+    max_length: Option<usize>,
+    charset: Charset,
+    // Position of the column in the primary key of the table, starting from 1
+    pk_position: Option<usize>,
+    collation: Option<String>,
+}
+
+// Columns of the table by their names
+pub type TableColumns = HashMap<String, ColumnInfo>;
+
+impl ColumnInfo {
+    pub fn new(sql_type: String, max_length: Option<usize>, charset: Charset) -> Self {
+        Self {
+            sql_type,
+            max_length,
+            charset,
+            pk_position: None,
+            collation: None,
+        }
+    }
+
+    pub fn with_pk_position(mut self, pk_position: Option<usize>) -> Self {
+        self.pk_position = pk_position;
+        self
+    }
+
+    pub fn with_collation(mut self, collation: Option<String>) -> Self {
+        self.collation = collation;
+        self
+    }
+
+    pub fn get_sql_type(&self) -> &String {
+        &self.sql_type
+    }
+
+    pub fn get_collation(&self) -> Option<&String> {
+        self.collation.as_ref()
+    }
+
+    // Makes the generated text fit the column, so that it's neither rejected by the DB in strict
+    // mode nor silently mangled by it otherwise. Returns the reason the value doesn't fit if the
+    // policy doesn't allow changing it. Values of other kinds are written as is.
+    pub fn fit(
+        &self,
+        val: GeneratedValue,
+        policy: OverflowPolicy,
+    ) -> Result<GeneratedValue, String> {
+        let GeneratedValue::String(s) = val else {
+            return Ok(val);
+        };
+        let mut fitted = s;
+        if !fitted.chars().all(|c| self.charset.can_encode(c)) {
+            if policy == OverflowPolicy::Reject {
+                return Err(format!(
+                    "it has characters that {} can't hold",
+                    self.charset
+                ));
+            }
+            fitted = fitted
+                .chars()
+                .map(|c| if self.charset.can_encode(c) { c } else { '?' })
+                .collect();
+        }
+        if let Some(max) = self
+            .max_length
+            .filter(|max| self.charset.len(&fitted) > *max)
+        {
+            if policy == OverflowPolicy::Reject {
+                return Err(format!("it's longer than {max} {}", self.length_unit()));
+            }
+            fitted = self.truncate(&fitted, max);
+        }
+        Ok(GeneratedValue::String(fitted))
+    }
+
+    fn truncate(&self, s: &str, max: usize) -> String {
+        match self.charset {
+            // Bytes are cut on the character boundary, so that the value is still valid text
+            Charset::Binary => {
+                let mut end = max;
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                s[..end].to_string()
+            }
+            _ => s.chars().take(max).collect(),
+        }
+    }
+
+    fn length_unit(&self) -> &'static str {
+        match self.charset {
+            Charset::Binary => "bytes",
+            _ => "characters",
+        }
+    }
+}
+
+impl FieldColumn for ColumnInfo {
+    fn fit(&self, val: GeneratedValue, policy: OverflowPolicy) -> Result<GeneratedValue, String> {
+        ColumnInfo::fit(self, val, policy)
+    }
+
+    // Case insensitive collations of MySQL (*_ci) compare values regardless of their case
+    fn unique_key(&self, val: String) -> String {
+        match &self.collation {
+            Some(collation) if collation.ends_with("_ci") => val.to_lowercase(),
+            _ => val,
+        }
+    }
+}
+
+// Every generator produces text, which could be stored as is in text and binary columns, but
+// would either fail to be written or get silently converted to something else in columns of
+// any other type.
+fn field_fits(class: ColumnClass) -> bool {
+    matches!(
+        class,
+        ColumnClass::Text | ColumnClass::Binary | ColumnClass::Untyped
+    )
+}
+
+fn pk_fits(pk_type: &PkType, class: ColumnClass) -> bool {
+    match pk_type {
+        PkType::Int | PkType::UnsignedBigInt => {
+            matches!(class, ColumnClass::Integer | ColumnClass::Untyped)
+        }
+        PkType::String => matches!(class, ColumnClass::Text | ColumnClass::Untyped),
+        PkType::Uuid => matches!(
+            class,
+            ColumnClass::Uuid | ColumnClass::Text | ColumnClass::Binary | ColumnClass::Untyped
+        ),
+        PkType::Binary => matches!(class, ColumnClass::Binary | ColumnClass::Untyped),
+    }
+}
+
+// Key type that is used for the key column of the SQL type when it's left out of config
+pub fn pk_type_from_sql(sql_type: &str) -> Option<PkType> {
+    let lower = sql_type.to_lowercase();
+    match classify_column_type(sql_type) {
+        ColumnClass::Integer if lower.contains("bigint") && lower.contains("unsigned") => {
+            Some(PkType::UnsignedBigInt)
+        }
+        ColumnClass::Integer | ColumnClass::Untyped => Some(PkType::Int),
+        ColumnClass::Text => Some(PkType::String),
+        ColumnClass::Uuid => Some(PkType::Uuid),
+        ColumnClass::Binary => Some(PkType::Binary),
+        _ => None,
+    }
+}
+
+pub fn pk_type_name(pk_type: &PkType) -> &'static str {
+    match pk_type {
+        PkType::Int => "int",
+        PkType::UnsignedBigInt => "unsigned_bigint",
+        PkType::String => "string",
+        PkType::Uuid => "uuid",
+        PkType::Binary => "binary",
+    }
+}
+
+// Compares the entity with the actual columns of its table (column name to SQL type) and
+// describes every key column and field that is either missing or has a type the configured
+// values don't fit, so that all of them could be reported at once.
+pub fn find_mismatches(masker_entity: &Entity, columns: &TableColumns) -> Vec<String> {
+    let t_name = masker_entity.get_table_name();
+    let missing_pk = masker_entity
+        .get_pk()
+        .is_empty()
+        .then(|| format!("{t_name}: primary key is neither configured nor read from the DB"));
+    let pk = masker_entity.get_pk().iter().filter_map(|c| {
+        let name = c.get_name();
+        match (columns.get(name), c.get_type()) {
+            (None, _) => Some(format!("{t_name}.{name}: primary key column not found")),
+            (Some(_), None) => Some(format!(
+                "{t_name}.{name}: type of primary key column is neither configured nor read from the DB"
+            )),
+            (Some(column), Some(pk_type))
+                if !pk_fits(pk_type, classify_column_type(&column.sql_type)) =>
+            {
+                Some(format!(
+                    "{t_name}.{name}: primary key of type {} doesn't fit column of type {}",
+                    pk_type_name(pk_type),
+                    column.sql_type
+                ))
+            }
+            (Some(_), Some(_)) => None,
+        }
+    });
+    let fields = masker_entity.get_entries().iter().filter_map(|f| {
+        let name = f.get_column_name();
+        match columns.get(name) {
+            None => Some(format!("{t_name}.{name}: column not found")),
+            Some(column) if !field_fits(classify_column_type(&column.sql_type)) => Some(format!(
+                "{t_name}.{name}: values of {:?} don't fit column of type {}",
+                f.kind, column.sql_type
+            )),
+            Some(_) => None,
+        }
+    });
+    missing_pk.into_iter().chain(pk).chain(fields).collect()
+}
+
+// Primary key of the entity with every part that's left out of config read from the columns of
+// the table. Key that is configured has to be the primary key of the table, unless the table
+// doesn't have one, in which case any unique columns could serve as the key. Columns of the
+// configured key could be listed in any order, but the key is always laid out in the order of
+// the primary key of the table, so that batches are paged by its index.
+pub fn resolve_pk(masker_entity: &Entity, columns: &TableColumns) -> Result<Vec<PkColumn>, String> {
+    let t_name = masker_entity.get_table_name();
+    if columns.is_empty() {
+        return Err(format!("{t_name}: table not found"));
+    }
+    let mut table_pk: Vec<(usize, &String)> = columns
+        .iter()
+        .filter_map(|(name, c)| c.pk_position.map(|pos| (pos, name)))
+        .collect();
+    table_pk.sort();
+    let table_pk: Vec<&String> = table_pk.into_iter().map(|(_, name)| name).collect();
+    let configured = masker_entity.get_pk();
+    let configured_names: Vec<&String> = configured.iter().map(|c| c.get_name()).collect();
+    let names = match (configured_names.is_empty(), table_pk.is_empty()) {
+        (true, true) => {
+            return Err(format!(
+                "{t_name}: table has no primary key, so it has to be configured"
+            ))
+        }
+        (false, false) if !same_names(&configured_names, &table_pk) => {
+            return Err(format!(
+                "{t_name}: configured key ({}) doesn't match primary key ({}) of the table",
+                join_names(&configured_names),
+                join_names(&table_pk)
+            ))
+        }
+        (_, false) => table_pk,
+        (false, true) => configured_names,
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            let configured_type = configured
+                .iter()
+                .find(|c| c.get_name() == name)
+                .and_then(|c| c.get_type());
+            let pk_type = match (configured_type, columns.get(name)) {
+                (Some(pk_type), _) => *pk_type,
+                (None, None) => {
+                    return Err(format!("{t_name}.{name}: primary key column not found"))
+                }
+                (None, Some(column)) => pk_type_from_sql(&column.sql_type).ok_or_else(|| {
+                    format!(
+                        "{t_name}.{name}: type {} of primary key column isn't supported",
+                        column.sql_type
+                    )
+                })?,
+            };
+            Ok(PkColumn::new(name.clone(), pk_type))
+        })
+        .collect()
+}
+
+// Compares the names regardless of their order
+fn same_names(configured: &[&String], table_pk: &[&String]) -> bool {
+    configured.len() == table_pk.len() && configured.iter().all(|n| table_pk.contains(n))
+}
+
+fn join_names(names: &[&String]) -> String {
+    names
+        .iter()
+        .map(|n| n.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::masker::generator::{
+        GeneratedValue, GeneratorErrorKind, Locale, Options, SimpleGenerator,
+    };
+    use crate::masker::{Field, FieldKind, PkColumn};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn get_field(name: &str, kind: FieldKind) -> Field {
+        Field::new(
+            name.to_string(),
+            kind,
+            Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                Ok(GeneratedValue::String("John".to_string()))
+            })),
+        )
+    }
+
+    #[test]
+    fn column_types_of_every_db_are_classified() {
+        for (sql_type, class) in [
+            ("varchar", ColumnClass::Text),
+            ("VARCHAR(50)", ColumnClass::Text),
+            ("character varying", ColumnClass::Text),
+            ("int", ColumnClass::Integer),
+            ("UNSIGNED BIG INT", ColumnClass::Integer),
+            ("bigint unsigned", ColumnClass::Integer),
+            ("numeric(10,2)", ColumnClass::Decimal),
+            ("date", ColumnClass::Temporal),
+            ("timestamp without time zone", ColumnClass::Temporal),
+            ("interval", ColumnClass::Temporal),
+            ("bytea", ColumnClass::Binary),
+            ("uuid", ColumnClass::Uuid),
+            ("point", ColumnClass::Other),
+            ("jsonb", ColumnClass::Other),
+            ("bigint(20) unsigned", ColumnClass::Integer),
+            ("enum('text','char')", ColumnClass::Other),
+            ("", ColumnClass::Untyped),
+        ] {
+            assert_eq!(classify_column_type(sql_type), class, "{sql_type}");
+        }
+    }
+
+    #[test]
+    fn every_mismatch_of_entity_is_reported() {
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![
+                PkColumn::new("id".to_string(), PkType::Int),
+                PkColumn::new("branch".to_string(), PkType::String),
+            ],
+            vec![
+                get_field("first_name", FieldKind::FirstName),
+                get_field("iban", FieldKind::Iban),
+                get_field("birthday", FieldKind::Template),
+                get_field("nickname", FieldKind::Template),
+            ],
+        );
+        let columns = HashMap::from_iter(
+            [
+                ("id", "varchar"),
+                ("first_name", "varchar(50)"),
+                ("iban", "int"),
+                ("birthday", "date"),
+            ]
+            .map(|(c, t)| {
+                (
+                    c.to_string(),
+                    ColumnInfo::new(t.to_string(), None, Charset::Utf8),
+                )
+            }),
+        );
+        assert_eq!(
+            find_mismatches(&entity, &columns),
+            vec![
+                "customers.id: primary key of type int doesn't fit column of type varchar",
+                "customers.branch: primary key column not found",
+                "customers.iban: values of Iban don't fit column of type int",
+                "customers.birthday: values of Template don't fit column of type date",
+                "customers.nickname: column not found",
+            ]
+        );
+    }
+
+    #[test]
+    fn generated_text_is_fitted_to_column() {
+        let column = ColumnInfo::new("varchar".to_string(), Some(5), Charset::Latin1);
+        let fitted = column
+            .fit(
+                GeneratedValue::String("Łódź-city".to_string()),
+                OverflowPolicy::Truncate,
+            )
+            .unwrap();
+        assert_eq!(fitted, GeneratedValue::String("?ód?-".to_string()));
+        let number = GeneratedValue::Number("1234567".to_string());
+        assert_eq!(
+            column.fit(number.clone(), OverflowPolicy::Reject).unwrap(),
+            number
+        );
+
+        let column = ColumnInfo::new("varbinary".to_string(), Some(3), Charset::Binary);
+        let fitted = column
+            .fit(
+                GeneratedValue::String("aéb".to_string()),
+                OverflowPolicy::Truncate,
+            )
+            .unwrap();
+        assert_eq!(fitted, GeneratedValue::String("aé".to_string()));
+    }
+
+    #[test]
+    fn values_that_dont_fit_are_rejected() {
+        let column = ColumnInfo::new("varchar".to_string(), Some(5), Charset::Ascii);
+        let reject = |s: &str| {
+            column
+                .fit(
+                    GeneratedValue::String(s.to_string()),
+                    OverflowPolicy::Reject,
+                )
+                .unwrap_err()
+        };
+        assert_eq!(reject("Zoë"), "it has characters that ascii can't hold");
+        assert_eq!(reject("Amsterdam"), "it's longer than 5 characters");
+        assert_eq!(
+            column
+                .fit(
+                    GeneratedValue::String("Paris".to_string()),
+                    OverflowPolicy::Reject
+                )
+                .unwrap(),
+            GeneratedValue::String("Paris".to_string())
+        );
+    }
+
+    fn get_unique_field(template: &str) -> Field {
+        let yaml = serde_yaml::from_str(&format!(
+            "name: name\nkind: Template\ntemplate: \"{template}\"\nunique: true"
+        ))
+        .unwrap();
+        Field::new_from_yaml(&yaml, Locale::default()).unwrap()
+    }
+
+    #[test]
+    fn unique_values_are_checked_once_fitted() {
+        // Every generated value is truncated to the same one
+        let field = get_unique_field("Carine%{dd}");
+        let column = ColumnInfo::new("varchar(6)".to_string(), Some(6), Charset::Utf8);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut generate = || field.generate(&HashMap::new(), None, &mut rng, Some(&column));
+        assert_eq!(generate(), Ok(GeneratedValue::String("Carine".to_string())));
+        assert_eq!(
+            generate().unwrap_err().kind,
+            GeneratorErrorKind::UniqueValuesExhausted(100)
+        );
+    }
+
+    #[test]
+    fn unique_values_are_compared_by_collation() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for (collation, is_unique) in [("utf8mb4_0900_ai_ci", false), ("utf8mb4_bin", true)] {
+            let field = get_unique_field("Carine");
+            let column = ColumnInfo::new("varchar(50)".to_string(), Some(50), Charset::Utf8)
+                .with_collation(Some(collation.to_string()));
+            field.reserve_values(vec!["CARINE".to_string()], Some(&column));
+            assert_eq!(
+                field
+                    .generate(&HashMap::new(), None, &mut rng, Some(&column))
+                    .is_ok(),
+                is_unique,
+                "{collation}"
+            );
+        }
+    }
+
+    fn get_columns(columns: &[(&str, &str, Option<usize>)]) -> TableColumns {
+        columns
+            .iter()
+            .map(|(c, t, pk)| {
+                let column = ColumnInfo::new(t.to_string(), None, Charset::Utf8);
+                (c.to_string(), column.with_pk_position(*pk))
+            })
+            .collect()
+    }
+
+    fn get_entity(pk: Vec<PkColumn>) -> Entity {
+        Entity::new("payments".to_string(), pk, vec![])
+    }
+
+    #[test]
+    fn primary_key_is_read_from_columns_when_not_configured() {
+        let columns = get_columns(&[
+            ("checkNumber", "varchar(50)", Some(2)),
+            ("customerNumber", "bigint unsigned", Some(1)),
+            ("amount", "decimal(10,2)", None),
+        ]);
+        let pk = resolve_pk(&get_entity(vec![]), &columns).unwrap();
+        assert_eq!(
+            pk.iter()
+                .map(|c| (c.get_name().as_str(), *c.get_type().unwrap()))
+                .collect::<Vec<(&str, PkType)>>(),
+            vec![
+                ("customerNumber", PkType::UnsignedBigInt),
+                ("checkNumber", PkType::String)
+            ]
+        );
+    }
+
+    #[test]
+    fn configured_key_has_to_match_primary_key() {
+        let columns = get_columns(&[
+            ("customerNumber", "int", Some(1)),
+            ("checkNumber", "varchar(50)", Some(2)),
+        ]);
+        let configured = vec![PkColumn::new("customerNumber".to_string(), PkType::Int)];
+        assert_eq!(
+            resolve_pk(&get_entity(configured), &columns).err().unwrap(),
+            "payments: configured key (customerNumber) doesn't match primary key (customerNumber, checkNumber) of the table"
+        );
+
+        // Configured key is laid out in the order of the primary key of the table
+        let configured = vec![
+            PkColumn::new("checkNumber".to_string(), PkType::String),
+            PkColumn::new("customerNumber".to_string(), PkType::Int),
+        ];
+        let pk = resolve_pk(&get_entity(configured), &columns).unwrap();
+        assert_eq!(
+            pk.iter()
+                .map(|c| c.get_name().as_str())
+                .collect::<Vec<&str>>(),
+            vec!["customerNumber", "checkNumber"]
+        );
+
+        // Any unique column could serve as the key of the table without primary key
+        let columns = get_columns(&[("checkNumber", "varchar(50)", None)]);
+        let configured = vec![PkColumn::new("checkNumber".to_string(), PkType::String)];
+        assert_eq!(
+            resolve_pk(&get_entity(configured), &columns).unwrap().len(),
+            1
+        );
+        assert_eq!(
+            resolve_pk(&get_entity(vec![]), &columns).err().unwrap(),
+            "payments: table has no primary key, so it has to be configured"
+        );
+    }
+}
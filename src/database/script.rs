@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::database::error::DatabaseAdapterError;
+use crate::masker::generator::GeneratedValue;
+
+// Statement as it's sent to the DB: the query with placeholders and the parameters to bind
+pub type Statement = (String, Vec<GeneratedValue>);
+
+// Where the statements that mask the data end up: they're either executed right away or written
+// to the script that is reviewed and applied to the DB by someone else.
+#[derive(Clone, Copy)]
+pub enum Output<'a> {
+    Database,
+    Script(&'a ScriptWriter),
+}
+
+pub struct ScriptWriter {
+    file: Mutex<File>,
+}
+
+impl ScriptWriter {
+    pub fn create(path: &Path) -> Result<Self, DatabaseAdapterError> {
+        let mut file = File::create(path).map_err(DatabaseAdapterError::failed_to_write_script)?;
+        writeln!(
+            file,
+            "-- Masking script generated by masker. Every block can be applied on its own."
+        )
+        .map_err(DatabaseAdapterError::failed_to_write_script)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    // Statements are written in one go, so that blocks written by the workers that mask
+    // different tables concurrently never get mixed up.
+    pub fn write_statements(&self, statements: &[String]) -> Result<(), DatabaseAdapterError> {
+        let block: String = statements
+            .iter()
+            .map(|s| format!("{s};\n"))
+            .chain(std::iter::once(String::from("\n")))
+            .collect();
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(block.as_bytes())
+            .map_err(DatabaseAdapterError::failed_to_write_script)
+    }
+}
+
+// Replaces the placeholders (either "?" or "$n") with the literals of the parameters, so that the
+// statement could be written to the script. Placeholders are only looked for outside of quoted
+// identifiers, so that column names containing "?" or "$" are left intact. Placeholder without
+// the matching parameter is reported rather than written to the script as is.
+pub fn inline_params(
+    query: &str,
+    args: &[GeneratedValue],
+    ident_quote: char,
+    literal: fn(&GeneratedValue) -> String,
+) -> Result<String, DatabaseAdapterError> {
+    let mut out = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    let mut next_arg = args.iter();
+    let mut in_ident = false;
+    while let Some(c) = chars.next() {
+        if c == ident_quote {
+            in_ident = !in_ident;
+        }
+        if in_ident || !matches!(c, '?' | '$') {
+            out.push(c);
+            continue;
+        }
+        if c == '?' {
+            let arg = next_arg
+                .next()
+                .ok_or_else(|| DatabaseAdapterError::missing_parameter(String::from("?")))?;
+            out.push_str(&literal(arg));
+            continue;
+        }
+        let mut idx = String::new();
+        while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {
+            idx.push(d);
+        }
+        if idx.is_empty() {
+            out.push(c);
+            continue;
+        }
+        let arg = idx
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| args.get(i.checked_sub(1)?))
+            .ok_or_else(|| DatabaseAdapterError::missing_parameter(format!("${idx}")))?;
+        out.push_str(&literal(arg));
+    }
+    Ok(out)
+}
+
+// Wraps the string into single quotes, doubling the quotes inside, which is how string literals
+// are escaped in standard SQL.
+pub fn quote_string_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+// Number values are written to the script as is only if they actually are numbers, anything else
+// is quoted.
+pub fn is_numeric_literal(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let mut parts = digits.splitn(2, '.');
+    parts.all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use sqlx::Row;
+
+    use super::*;
+    use crate::database::checkpoint::Checkpoint;
+    use crate::database::error::DatabaseAdapterErrorKind;
+    use crate::database::shared;
+    use crate::database::sqlite::test_db::{get_adapter, get_test_conn};
+    use crate::masker::generator::{Options, SimpleGenerator};
+    use crate::masker::{Entity, Field, FieldKind, PkColumn, PkType};
+
+    fn literal(val: &GeneratedValue) -> String {
+        match val {
+            GeneratedValue::Number(n) if is_numeric_literal(n) => n.clone(),
+            GeneratedValue::Number(s) | GeneratedValue::String(s) => quote_string_literal(s),
+            GeneratedValue::Bytes(_) => String::from("X''"),
+            GeneratedValue::Null => String::from("NULL"),
+        }
+    }
+
+    #[test]
+    fn params_are_inlined_as_literals() {
+        let args = [
+            GeneratedValue::Number("1".to_string()),
+            GeneratedValue::String("O'Neil".to_string()),
+        ];
+        assert_eq!(
+            inline_params(
+                "INSERT INTO `t` (`a?`, `b`) VALUES (?, ?)",
+                &args,
+                '`',
+                literal
+            )
+            .unwrap(),
+            "INSERT INTO `t` (`a?`, `b`) VALUES (1, 'O''Neil')"
+        );
+        assert_eq!(
+            inline_params(
+                r#"UPDATE "t$1" SET "b""$2" = CAST($2 AS text) WHERE "a" = CAST($1 AS integer)"#,
+                &args,
+                '"',
+                literal
+            )
+            .unwrap(),
+            r#"UPDATE "t$1" SET "b""$2" = CAST('O''Neil' AS text) WHERE "a" = CAST(1 AS integer)"#
+        );
+    }
+
+    #[test]
+    fn placeholders_without_params_are_reported() {
+        let args = [GeneratedValue::Number("1".to_string())];
+        for (query, placeholder) in [
+            ("VALUES (?, ?)", "?"),
+            ("VALUES ($0)", "$0"),
+            ("VALUES ($1, $2)", "$2"),
+        ] {
+            let err = inline_params(query, &args, '"', literal).unwrap_err();
+            assert!(
+                matches!(&err.kind, DatabaseAdapterErrorKind::MissingParameter(p) if p == placeholder),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn only_numbers_are_written_unquoted() {
+        for n in ["1", "-12", "3.14"] {
+            assert!(is_numeric_literal(n), "{n}");
+        }
+        for n in ["", "-", "1.", ".5", "1e5", "1; DROP TABLE t", "0x10"] {
+            assert!(!is_numeric_literal(n), "{n}");
+        }
+    }
+
+    #[test]
+    fn script_consists_of_written_blocks() {
+        let path = std::env::temp_dir().join(format!("masker_script_{}.sql", std::process::id()));
+        let script = ScriptWriter::create(&path).unwrap();
+        script
+            .write_statements(&["BEGIN".to_string(), "COMMIT".to_string()])
+            .unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("-- "));
+        assert!(content.ends_with("\nBEGIN;\nCOMMIT;\n\n"), "{content}");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn exported_statements_can_be_applied_later() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let path = std::env::temp_dir().join(format!("masker_export_{}.sql", std::process::id()));
+        let script = ScriptWriter::create(&path).unwrap();
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new(
+                "contactFirstName".to_string(),
+                FieldKind::Template,
+                Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                    Ok(GeneratedValue::String(r#"O'Neil "Jr" \ ; --"#.to_string()))
+                })),
+            )],
+        );
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Script(&script),
+        )
+        .await
+        .unwrap();
+        let count_masked =
+            "SELECT COUNT(*) FROM customers WHERE contactFirstName = 'O''Neil \"Jr\" \\ ; --'";
+        let masked = sqlx::query(count_masked)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get::<i64, _>(0);
+        assert_eq!(masked, 0);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(
+            content.contains("VALUES (103, 'O''Neil \"Jr\" \\ ; --')"),
+            "{content}"
+        );
+        sqlx::raw_sql(content.as_str())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let masked = sqlx::query(count_masked)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get::<i64, _>(0);
+        assert_eq!(masked, 4);
+        std::fs::remove_file(path).unwrap();
+    }
+}
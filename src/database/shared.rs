@@ -1,4 +1,351 @@
-use crate::masker::error::ConfigParseError;
+use async_trait::async_trait;
+use futures::{stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::masker::{
+    error::ConfigParseError,
+    generator::{GeneratedValue, GeneratorErrorKind, Options, ORIGINAL_VALUE_PREFIX},
+    Entity, FieldColumn, Masker, PkColumn, PkType,
+};
+
+use super::adapter::DatabaseAdapter;
+use super::checkpoint::{Checkpoint, Progress};
+use super::discovery::{self, DiscoveredEntity, TableCatalog};
+use super::error::{DatabaseAdapterError, DatabaseAdapterErrorKind};
+use super::preview::{EntityPreview, SAMPLE_SIZE};
+use super::scheduler::Scheduler;
+use super::schema::{self, TableColumns};
+use super::script::{Output, ScriptWriter, Statement};
+
+// Everything that differs between the DBs: how to connect to them and the SQL that is run
+// against them. Flows built on top of it (verification, masking, previews...) are the same for
+// every DB.
+#[async_trait]
+pub trait Dialect: Sync {
+    type Pool: Send + Sync;
+    // Limit of the parameters bound to a single statement
+    const MAX_PARAMS: usize;
+
+    fn get_scheduler(&self) -> &Scheduler;
+    // Tells whether the identifier could be safely quoted and used in a query
+    fn is_valid_identifier(ident: &str) -> bool;
+    // Sessions of the dry run are read only, so that nothing could be written even by mistake
+    async fn connect(&self, read_only: bool) -> Result<Self::Pool, DatabaseAdapterError>;
+    // Columns of the table of the entity, none if there is no such table
+    async fn get_columns(
+        &self,
+        masker_entity: &Entity,
+        p: &Self::Pool,
+    ) -> Result<TableColumns, sqlx::Error>;
+    // Number of rows of the entity that would be masked
+    async fn get_total_size(
+        &self,
+        masker_entity: &Entity,
+        p: &Self::Pool,
+    ) -> Result<i64, sqlx::Error>;
+    // Batches are paged by the key (keyset pagination) rather than by offset, so that every
+    // batch is fetched with an index range scan no matter how deep into the table it is, and
+    // rows don't get skipped or repeated if the table changes while being masked. Current values
+    // of the masked columns are read as text along with the key.
+    async fn get_batch_to_update(
+        &self,
+        masker_entity: &Entity,
+        p: &Self::Pool,
+        table_columns: &TableColumns,
+        b_size: i64,
+        last_key: Option<&[GeneratedValue]>,
+    ) -> Result<Vec<BatchRow>, sqlx::Error>;
+    // Describes every table of the DB for discovery
+    async fn get_catalog(&self, p: &Self::Pool) -> Result<Vec<TableCatalog>, sqlx::Error>;
+    // Reads every distinct value of the column as text, NULL aside
+    async fn get_column_values(
+        &self,
+        masker_entity: &Entity,
+        column: &str,
+        p: &Self::Pool,
+    ) -> Result<Vec<String>, sqlx::Error>;
+    // Reads the first rows of the text columns of the table as text
+    async fn get_column_samples(
+        &self,
+        catalog: &TableCatalog,
+        p: &Self::Pool,
+        limit: i64,
+    ) -> Result<Vec<Vec<Option<String>>>, sqlx::Error>;
+    // Statement that writes the masked values of the rows. Generated values never end up in the
+    // query text, but are returned separately to be bound as query parameters, so that they could
+    // safely contain any characters. Parameters are returned in the same order as placeholders
+    // appear in the query.
+    fn prepare_batch_query(
+        &self,
+        masker_entity: &Entity,
+        table_columns: &TableColumns,
+        batch: &[BatchRow],
+    ) -> Result<Statement, DatabaseAdapterError>;
+    // Runs the statements that mask the batch within a single transaction
+    async fn write_batch(
+        &self,
+        masker_entity: &Entity,
+        p: &Self::Pool,
+        table_columns: &TableColumns,
+        statements: &[Statement],
+    ) -> Result<(), DatabaseAdapterError>;
+    // The same statements as they are written to the exported script, where every batch is a
+    // block that could be applied on its own
+    fn script_block(
+        &self,
+        masker_entity: &Entity,
+        table_columns: &TableColumns,
+        statements: &[Statement],
+    ) -> Result<Vec<String>, DatabaseAdapterError>;
+}
+
+#[async_trait]
+impl<D: Dialect + Send> DatabaseAdapter for D {
+    async fn apply_mask(
+        &self,
+        masker: &mut Masker,
+        checkpoint: &Checkpoint,
+    ) -> Result<(), DatabaseAdapterError> {
+        run_mask(self, masker, checkpoint, Output::Database).await
+    }
+
+    async fn export_mask(
+        &self,
+        masker: &mut Masker,
+        script: &ScriptWriter,
+    ) -> Result<(), DatabaseAdapterError> {
+        run_mask(self, masker, &Checkpoint::new(None), Output::Script(script)).await
+    }
+
+    async fn preview_mask(
+        &self,
+        masker: &mut Masker,
+    ) -> Result<Vec<EntityPreview>, DatabaseAdapterError> {
+        preview_mask(self, masker).await
+    }
+
+    async fn discover(&self) -> Result<Vec<DiscoveredEntity>, DatabaseAdapterError> {
+        discover(self).await
+    }
+}
+
+async fn run_mask<D: Dialect>(
+    dialect: &D,
+    masker: &mut Masker,
+    checkpoint: &Checkpoint,
+    output: Output<'_>,
+) -> Result<(), DatabaseAdapterError> {
+    validate_identifiers(masker, D::is_valid_identifier)?;
+    // Exported statements are applied by someone else, so the DB is only read
+    let pool = dialect.connect(matches!(output, Output::Script(_))).await?;
+    resolve_keys(dialect, masker, &pool).await?;
+    verify_entities(dialect, masker, &pool).await?;
+    // Futures are collected up front, since they do nothing until polled by the scheduler
+    let tasks: Vec<_> = masker
+        .get_entities()
+        .iter()
+        .map(|entity| mask_table(dialect, entity, &pool, checkpoint, output))
+        .collect();
+    // Progress is saved even if masking has failed, so that it could be resumed from there
+    let masked = dialect.get_scheduler().run_all(tasks).await;
+    let saved = checkpoint.flush();
+    masked.and(saved)
+}
+
+// Masks the table batch by batch, starting after the last batch saved in the checkpoint. Batches
+// are fetched one after another, since every batch starts after the last key of the previous one,
+// but up to `workers` of them are written at the same time. Memory use is bounded by the batches
+// in flight rather than by the size of the table. Progress is saved in the order of the batches,
+// once every batch before it has been written, so that an interrupted run could be resumed from
+// there.
+pub async fn mask_table<D: Dialect>(
+    dialect: &D,
+    masker_entity: &Entity,
+    p: &D::Pool,
+    checkpoint: &Checkpoint,
+    output: Output<'_>,
+) -> Result<(), DatabaseAdapterError> {
+    let last_key = match checkpoint.get_progress(masker_entity)? {
+        Some(Progress::Done) => return Ok(()),
+        Some(Progress::MaskedUpTo(key)) => Some(key),
+        None => None,
+    };
+    let table_columns = &dialect
+        .get_columns(masker_entity, p)
+        .await
+        .map_err(DatabaseAdapterError::failed_query)?;
+    // Every value in the column is reserved by a unique field, since the rows that don't get masked
+    // (filtered out, kept or not reached yet) still hold theirs when the masked ones are written
+    for field in masker_entity.get_entries().iter().filter(|f| f.is_unique()) {
+        let values = dialect
+            .get_column_values(masker_entity, field.get_column_name(), p)
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        let column = table_columns.get(field.get_column_name());
+        field.reserve_values(values, column.map(|c| c as &dyn FieldColumn));
+    }
+    let scheduler = dialect.get_scheduler();
+    let rows_per_query = rows_per_statement(masker_entity, D::MAX_PARAMS);
+    let b_size = scheduler.get_batch_size();
+    // Fetched batches wait here for a free worker, so that only one batch is read ahead
+    let (tx, mut rx) = mpsc::channel::<Vec<BatchRow>>(1);
+    let fetch = async move {
+        let mut last_key = last_key;
+        loop {
+            let rows = scheduler
+                .run(dialect.get_batch_to_update(
+                    masker_entity,
+                    p,
+                    table_columns,
+                    b_size,
+                    last_key.as_deref(),
+                ))
+                .await
+                .map_err(DatabaseAdapterError::failed_query)?;
+            // Incomplete batch means that the end of the table has been reached
+            let is_last = (rows.len() as i64) < b_size;
+            last_key = rows.last().map(|(key, _)| key.clone());
+            // Sending fails only if writing has stopped on an error, which is reported by it
+            if rows.is_empty() || tx.send(rows).await.is_err() || is_last {
+                return Ok::<(), DatabaseAdapterError>(());
+            }
+        }
+    };
+    let written = stream::poll_fn(|cx| rx.poll_recv(cx))
+        .map(|rows| async move {
+            let statements = rows
+                .chunks(rows_per_query)
+                .map(|chunk| dialect.prepare_batch_query(masker_entity, table_columns, chunk))
+                .collect::<Result<Vec<Statement>, DatabaseAdapterError>>()?;
+            match output {
+                Output::Database => {
+                    scheduler
+                        .run(dialect.write_batch(masker_entity, p, table_columns, &statements))
+                        .await?
+                }
+                Output::Script(script) => {
+                    let block = dialect.script_block(masker_entity, table_columns, &statements)?;
+                    script.write_statements(&block)?
+                }
+            }
+            Ok(rows.into_iter().last().map(|(key, _)| key))
+        })
+        .buffered(scheduler.get_workers());
+    let write = async {
+        let mut written = std::pin::pin!(written);
+        while let Some(key) = written.next().await {
+            if let Some(key) = key? {
+                checkpoint.save_progress(masker_entity, Progress::MaskedUpTo(key))?;
+            }
+        }
+        Ok(())
+    };
+    futures::try_join!(fetch, write)?;
+
+    checkpoint.save_progress(masker_entity, Progress::Done)
+}
+
+// Samples every table of the catalog, so that its columns are judged by their values as well as
+// by their names
+pub async fn discover<D: Dialect>(
+    dialect: &D,
+) -> Result<Vec<DiscoveredEntity>, DatabaseAdapterError> {
+    let pool = dialect.connect(true).await?;
+    let catalogs = dialect
+        .get_catalog(&pool)
+        .await
+        .map_err(DatabaseAdapterError::failed_query)?;
+    let mut discovered = vec![];
+    for catalog in catalogs.iter() {
+        let samples = dialect
+            .get_column_samples(catalog, &pool, discovery::SAMPLE_SIZE)
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        discovered.extend(DiscoveredEntity::new(catalog, samples));
+    }
+    Ok(discovered)
+}
+
+// Shows what masking would do without writing anything: counts the rows of every entity and
+// masks a few of them in memory.
+pub async fn preview_mask<D: Dialect>(
+    dialect: &D,
+    masker: &mut Masker,
+) -> Result<Vec<EntityPreview>, DatabaseAdapterError> {
+    validate_identifiers(masker, D::is_valid_identifier)?;
+    let pool = dialect.connect(true).await?;
+    resolve_keys(dialect, masker, &pool).await?;
+    verify_entities(dialect, masker, &pool).await?;
+    let mut previews = vec![];
+    for entity in masker.get_entities() {
+        let total_rows = dialect
+            .get_total_size(entity, &pool)
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        let columns = dialect
+            .get_columns(entity, &pool)
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        // Samples are the first rows that masking would go through
+        let samples = dialect
+            .get_batch_to_update(entity, &pool, &columns, SAMPLE_SIZE, None)
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        previews.push(EntityPreview::new(entity, &columns, total_rows, samples)?);
+    }
+    Ok(previews)
+}
+
+// Fills in the primary key of every entity from the columns of its table. Every key that can't
+// be resolved is reported at once, the same way as the mismatches found by the verification.
+pub async fn resolve_keys<D: Dialect>(
+    dialect: &D,
+    masker: &mut Masker,
+    p: &D::Pool,
+) -> Result<(), DatabaseAdapterError> {
+    let mut mismatches = Vec::new();
+    for entity in masker.get_entities_mut() {
+        let columns = dialect
+            .get_columns(entity, p)
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        match schema::resolve_pk(entity, &columns) {
+            Ok(pk) => entity.set_pk(pk),
+            Err(e) => mismatches.push(e),
+        }
+    }
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(DatabaseAdapterError::schema_mismatch(mismatches))
+    }
+}
+
+// Compares every entity with the actual table before anything is masked, so that every missing
+// table, column and type mismatch is reported at once instead of failing halfway through.
+pub async fn verify_entities<D: Dialect>(
+    dialect: &D,
+    masker: &Masker,
+    p: &D::Pool,
+) -> Result<(), DatabaseAdapterError> {
+    let mut mismatches = Vec::new();
+    for entity in masker.get_entities() {
+        let columns = dialect
+            .get_columns(entity, p)
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        if columns.is_empty() {
+            mismatches.push(format!("{}: table not found", entity.get_table_name()));
+        } else {
+            mismatches.extend(schema::find_mismatches(entity, &columns));
+        }
+    }
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(DatabaseAdapterError::schema_mismatch(mismatches))
+    }
+}
 
 pub fn parse_runtime_env_values(
     yaml: &serde_yaml::Value,
@@ -72,3 +419,275 @@ pub fn read_str_or_int_field(
         }),
     }
 }
+
+// Reads optional positive integer, falling back to the default value if the field isn't set
+pub fn read_positive_int_field(
+    yaml: &serde_yaml::Mapping,
+    field: String,
+    default: u64,
+) -> Result<u64, ConfigParseError> {
+    if yaml.get(&field).is_none() {
+        return Ok(default);
+    }
+    let val = read_str_or_int_field(yaml, field.clone())?;
+    match val.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(ConfigParseError {
+            kind: crate::masker::error::ConfigParseErrorKind::UnexpectedFieldValue(val),
+            field,
+        }),
+    }
+}
+
+// Quotes identifier following the SQL standard (used by Postgres and SQLite).
+pub fn quote_identifier(ident: &str) -> String {
+    quote_identifier_with(ident, '"')
+}
+
+pub fn quote_table_name(t_name: &str) -> String {
+    quote_table_name_with(t_name, '"')
+}
+
+// Wraps identifier into the given quote char and escapes the same char inside by doubling it,
+// which is how both standard SQL ("...") and MySQL (`...`) escape quotes in identifiers.
+pub fn quote_identifier_with(ident: &str, quote: char) -> String {
+    format!(
+        "{quote}{}{quote}",
+        ident.replace(quote, format!("{quote}{quote}").as_str())
+    )
+}
+
+// Table names might be qualified with schema or database name (e.g. public.users), in which case
+// every part of the name is quoted separately.
+pub fn quote_table_name_with(t_name: &str, quote: char) -> String {
+    t_name
+        .split('.')
+        .map(|part| quote_identifier_with(part, quote))
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+// Splits the table name into the schema (or database) it's qualified with, if any, and the name
+// of the table itself, so that the table could be looked up in the catalog.
+pub fn split_table_name(t_name: &str) -> (Option<&str>, &str) {
+    match t_name.split_once('.') {
+        Some((schema, table)) => (Some(schema), table),
+        None => (None, t_name),
+    }
+}
+
+// Checks every identifier of every entity (table name, key and field columns) with the dialect
+// specific rule, so that names that can't be quoted safely are reported before anything is run
+// against the DB.
+pub fn validate_identifiers(
+    masker: &Masker,
+    is_valid: fn(&str) -> bool,
+) -> Result<(), DatabaseAdapterError> {
+    for entity in masker.get_entities() {
+        let t_name = entity.get_table_name();
+        let columns = entity
+            .get_pk()
+            .iter()
+            .map(|c| c.get_name())
+            .chain(entity.get_entries().iter().map(|f| f.get_column_name()));
+        for ident in t_name.split('.').chain(columns.map(|c| c.as_str())) {
+            if !is_valid(ident) {
+                return Err(DatabaseAdapterError::invalid_identifier(format!(
+                    "{ident:?} of entity {t_name}"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+const KEEP_FLAG_PREFIX: &str = "keep.";
+
+// BatchRow as it is read from DB: key values followed by the current values of the masked columns
+// read as text (None stands for NULL), in the same order as the fields are defined in the entity.
+pub type BatchRow = (Vec<GeneratedValue>, Vec<Option<String>>);
+
+// Runs generators of every entity field for a single row identified by the key. Key values and
+// the original values of the row (as orig.<column>) are passed to the generators as well, so
+// that templates could refer to them, while deterministic fields are also seeded from the original
+// value of their own column. Every value is fitted to its column before templates get to see it,
+// so that they refer to what is actually written. Values kept by keep_if rules are left as they
+// were read and are flagged, so that they are never written back. Generated values are returned
+// in the same order as the fields are defined in the entity.
+pub fn generate_entity_values<'a>(
+    masker_entity: &'a Entity,
+    columns: &TableColumns,
+    (key, originals): &BatchRow,
+) -> Result<Vec<(&'a String, GeneratedValue, bool)>, DatabaseAdapterError> {
+    let entity_fields = masker_entity.get_entries();
+    if entity_fields.is_empty() {
+        return Err(DatabaseAdapterError {
+            kind: DatabaseAdapterErrorKind::NoEntriesSpecifiedForEntity(
+                masker_entity.get_table_name(),
+            ),
+        });
+    }
+    let orig_names: Vec<String> = entity_fields
+        .iter()
+        .map(|f| format!("{ORIGINAL_VALUE_PREFIX}{}", f.get_column_name()))
+        .collect();
+    let mut opts: Options = masker_entity
+        .get_pk()
+        .iter()
+        .map(|c| c.get_name())
+        .zip(key.iter().cloned())
+        .chain(orig_names.iter().zip(originals).map(|(name, val)| {
+            let val = val
+                .clone()
+                .map_or(GeneratedValue::Null, GeneratedValue::String);
+            (name, val)
+        }))
+        .collect();
+    let mut rng = masker_entity.rng_for_row(key);
+    let mut kept = Vec::new();
+    for (i, entry) in entity_fields.iter().enumerate() {
+        let original = originals.get(i).and_then(|o| o.as_deref());
+        let failed_to_mask =
+            |e| DatabaseAdapterError::failed_to_mask(String::from(entry.get_column_name()), e);
+        if let Some(val) = entry.kept_value(&opts, original).map_err(failed_to_mask)? {
+            kept.push(entry.get_column_name());
+            opts.insert(entry.get_column_name(), val);
+            continue;
+        }
+        let column = columns.get(entry.get_column_name());
+        let val = entry
+            .generate(
+                &opts,
+                original,
+                &mut rng,
+                column.map(|c| c as &dyn FieldColumn),
+            )
+            .map_err(|e| match e.kind {
+                GeneratorErrorKind::ValueDoesntFitColumn(reason) => {
+                    DatabaseAdapterError::value_doesnt_fit_column(
+                        format!(
+                            "{}.{}",
+                            masker_entity.get_table_name(),
+                            entry.get_column_name()
+                        ),
+                        reason,
+                    )
+                }
+                _ => failed_to_mask(e),
+            })?;
+        opts.insert(entry.get_column_name(), val);
+    }
+    Ok(entity_fields
+        .iter()
+        .filter_map(|entry| {
+            let column = entry.get_column_name();
+            opts.remove(column)
+                .map(|val| (column, val, kept.contains(&column)))
+        })
+        .collect())
+}
+
+// WHERE clause that limits the rows to the ones matching the entity filter and the given
+// conditions. Filter is wrapped in parentheses, so that OR in it doesn't swallow the other
+// conditions. Clause ends with a space, unless there is nothing to filter by.
+pub fn where_clause(masker_entity: &Entity, conds: &[String]) -> String {
+    let conds = masker_entity
+        .get_filter()
+        .map(|f| format!("({f})"))
+        .into_iter()
+        .chain(conds.iter().cloned())
+        .collect::<Vec<String>>();
+    if conds.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {} ", conds.join(" AND "))
+    }
+}
+
+// Query that counts the rows of the entity that would be masked
+pub fn count_query(masker_entity: &Entity, quote_table_name: fn(&str) -> String) -> String {
+    format!(
+        "SELECT COUNT(*) FROM {} {}",
+        quote_table_name(&masker_entity.get_table_name()),
+        where_clause(masker_entity, &[])
+    )
+}
+
+// Wraps key value that was read from DB as text into the value of the key column type. Key of
+// unknown type is kept as text, which is how it was read.
+pub fn key_value_from_str(pk_column: &PkColumn, val: String) -> GeneratedValue {
+    match pk_column.get_type() {
+        Some(PkType::Int | PkType::UnsignedBigInt) => GeneratedValue::Number(val),
+        Some(PkType::String | PkType::Uuid | PkType::Binary) | None => GeneratedValue::String(val),
+    }
+}
+
+// Columns of the rows that are written to the DB in bulk: key columns go first, followed by the
+// masked ones in the same order as the fields are defined in the entity and by their keep flags.
+pub fn batch_columns(masker_entity: &Entity) -> Vec<String> {
+    masker_entity
+        .get_pk()
+        .iter()
+        .map(|c| c.get_name().clone())
+        .chain(
+            masker_entity
+                .get_entries()
+                .iter()
+                .map(|f| f.get_column_name().clone()),
+        )
+        .chain(keep_flag_columns(masker_entity))
+        .collect()
+}
+
+// Flag columns of the batch (as keep.<column>), one for every field with keep_if rules, telling
+// whether the value of the row is kept. Kept values are never written back, since they were read
+// as text and could change on the way back (binary columns, charsets, values longer than the
+// column).
+pub fn keep_flag_columns(masker_entity: &Entity) -> Vec<String> {
+    masker_entity
+        .get_entries()
+        .iter()
+        .filter(|f| f.has_keep_rules())
+        .map(|f| keep_flag_column(f.get_column_name()))
+        .collect()
+}
+
+pub fn keep_flag_column(column: &str) -> String {
+    format!("{KEEP_FLAG_PREFIX}{column}")
+}
+
+// Generates values for every row of the batch and lays them out row by row in the order of
+// batch_columns, so that they could be bound as parameters of a single multi-row statement.
+// Kept values are bound as NULL, since the column is left as it is for them anyway.
+pub fn generate_batch_values(
+    masker_entity: &Entity,
+    columns: &TableColumns,
+    rows: &[BatchRow],
+) -> Result<Vec<GeneratedValue>, DatabaseAdapterError> {
+    let mut args = Vec::with_capacity(rows.len() * batch_columns(masker_entity).len());
+    for row in rows {
+        let values = generate_entity_values(masker_entity, columns, row)?;
+        args.extend(row.0.iter().cloned());
+        let flags = values
+            .iter()
+            .zip(masker_entity.get_entries())
+            .filter(|(_, f)| f.has_keep_rules())
+            .map(|((_, _, kept), _)| {
+                GeneratedValue::Number(String::from(if *kept { "1" } else { "0" }))
+            })
+            .collect::<Vec<GeneratedValue>>();
+        args.extend(
+            values
+                .into_iter()
+                .map(|(_, val, kept)| if kept { GeneratedValue::Null } else { val }),
+        );
+        args.extend(flags);
+    }
+    Ok(args)
+}
+
+// Number of rows that can be written by a single statement without exceeding the limit of bound
+// parameters per statement of the DB.
+pub fn rows_per_statement(masker_entity: &Entity, max_params: usize) -> usize {
+    (max_params / batch_columns(masker_entity).len()).max(1)
+}
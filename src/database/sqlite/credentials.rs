@@ -0,0 +1,37 @@
+use sqlx::sqlite::SqliteConnectOptions;
+
+use crate::{database::shared, masker::error::ConfigParseError};
+
+// SQLite databases are plain files, so the only thing that is needed to connect is the path to
+// the database file.
+pub struct SQLiteConnectionCredentials {
+    path: String,
+}
+
+impl SQLiteConnectionCredentials {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+    pub fn from_yaml(
+        yaml: &serde_yaml::Value,
+    ) -> Result<SQLiteConnectionCredentials, ConfigParseError> {
+        match yaml["connection"].as_mapping() {
+            Some(m) => {
+                let path = shared::read_str_field(m, String::from("path"))?;
+                Ok(Self::new(path))
+            }
+            None => Err(ConfigParseError {
+                kind: crate::masker::error::ConfigParseErrorKind::MissingField,
+                field: String::from("connection"),
+            }),
+        }
+    }
+
+    // The database file is never created if it doesn't exist, since there is nothing to mask in
+    // an empty database and it most likely means that the path is wrong.
+    pub fn get_connect_options(&self) -> SqliteConnectOptions {
+        SqliteConnectOptions::new()
+            .filename(&self.path)
+            .create_if_missing(false)
+    }
+}
@@ -0,0 +1,7 @@
+mod credentials;
+mod sqlite_adapter;
+
+pub use sqlite_adapter::SQLiteAdapter;
+
+#[cfg(test)]
+pub use sqlite_adapter::test_db;
@@ -0,0 +1,1290 @@
+use super::credentials::SQLiteConnectionCredentials;
+use async_trait::async_trait;
+use sqlx::query::Query;
+use sqlx::sqlite::{SqliteArguments, SqliteRow};
+use sqlx::{Row, Sqlite, TypeInfo, ValueRef};
+
+use crate::database::discovery::TableCatalog;
+use crate::database::error::DatabaseAdapterError;
+use crate::database::scheduler::Scheduler;
+use crate::database::schema::{Charset, ColumnInfo, TableColumns};
+use crate::database::script::{self, Statement};
+use crate::database::shared::{self, quote_identifier, quote_table_name, BatchRow, Dialect};
+use crate::masker;
+use crate::masker::error::ConfigParseError;
+use crate::masker::generator::{encode_hex, GeneratedValue};
+
+// SQLite columns are dynamically typed, so the key is decoded based on the storage class of the
+// actual value rather than on the declared column type.
+fn decode_key_value(r: &SqliteRow, i: usize) -> Result<GeneratedValue, sqlx::Error> {
+    let type_name = r.try_get_raw(i)?.type_info().name().to_string();
+    match type_name.as_str() {
+        "INTEGER" => Ok(GeneratedValue::Number(r.try_get::<i64, _>(i)?.to_string())),
+        "BLOB" => Ok(GeneratedValue::Bytes(r.try_get::<Vec<u8>, _>(i)?)),
+        _ => Ok(GeneratedValue::String(r.try_get::<String, _>(i)?)),
+    }
+}
+
+fn bind_value<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    val: &'q GeneratedValue,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    match val {
+        GeneratedValue::Number(n) => match n.parse::<i64>() {
+            Ok(i) => query.bind(i),
+            Err(_) => query.bind(n.as_str()),
+        },
+        GeneratedValue::String(s) => query.bind(s.as_str()),
+        GeneratedValue::Bytes(b) => query.bind(b.as_slice()),
+        GeneratedValue::Null => query.bind(None::<String>),
+    }
+}
+
+// Renders the value as a literal of the exported script
+fn literal(val: &GeneratedValue) -> String {
+    match val {
+        GeneratedValue::Number(n) if script::is_numeric_literal(n) => n.clone(),
+        GeneratedValue::Number(s) | GeneratedValue::String(s) => script::quote_string_literal(s),
+        GeneratedValue::Bytes(b) => format!("X'{}'", encode_hex(b)),
+        GeneratedValue::Null => String::from("NULL"),
+    }
+}
+
+pub struct SQLiteAdapter {
+    connection_creds: SQLiteConnectionCredentials,
+    scheduler: Scheduler,
+}
+
+impl SQLiteAdapter {
+    pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
+        let connection_creds = SQLiteConnectionCredentials::from_yaml(yaml)?;
+        // SQLite allows only one writer at a time, so by default a single connection is used, since
+        // more of them would only end up waiting for the database lock.
+        let scheduler = Scheduler::new_from_yaml(yaml, 1)?;
+        Ok(SQLiteAdapter {
+            connection_creds,
+            scheduler,
+        })
+    }
+}
+
+#[async_trait]
+impl Dialect for SQLiteAdapter {
+    type Pool = sqlx::SqlitePool;
+    // Default limit of host parameters per statement of SQLite builds since 3.32
+    const MAX_PARAMS: usize = 32766;
+
+    fn get_scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    fn is_valid_identifier(ident: &str) -> bool {
+        !ident.is_empty() && !ident.contains('\0')
+    }
+
+    // Read only sessions open the database file read only
+    async fn connect(&self, read_only: bool) -> Result<Self::Pool, DatabaseAdapterError> {
+        sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(self.scheduler.get_pool_size())
+            .connect_with(
+                self.connection_creds
+                    .get_connect_options()
+                    .read_only(read_only),
+            )
+            .await
+            .map_err(DatabaseAdapterError::connection_error)
+    }
+
+    // Reads the declared types of the table columns, so that the config could be verified before
+    // any row gets masked, along with the position of the column in the primary key. SQLite
+    // never enforces the declared length of the column and stores any text as is, so generated
+    // values always fit. Schema the table is qualified with goes to the second argument of the
+    // pragma, since the first one is only the name of the table.
+    async fn get_columns(
+        &self,
+        masker_entity: &masker::Entity,
+        p: &Self::Pool,
+    ) -> Result<TableColumns, sqlx::Error> {
+        let t_name = masker_entity.get_table_name();
+        let query = match shared::split_table_name(&t_name) {
+            (Some(schema), table) => {
+                sqlx::query("SELECT name, type, pk FROM pragma_table_info(?, ?);")
+                    .bind(table)
+                    .bind(schema)
+            }
+            (None, table) => {
+                sqlx::query("SELECT name, type, pk FROM pragma_table_info(?);").bind(table)
+            }
+        };
+        Ok(query
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| {
+                let pk_position = Some(r.get::<i64, _>(2) as usize).filter(|p| *p > 0);
+                let column = ColumnInfo::new(r.get::<String, _>(1), None, Charset::Utf8)
+                    .with_pk_position(pk_position);
+                (r.get::<String, _>(0), column)
+            })
+            .collect())
+    }
+
+    async fn get_total_size(
+        &self,
+        masker_entity: &masker::Entity,
+        p: &Self::Pool,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query(shared::count_query(masker_entity, quote_table_name).as_str())
+            .fetch_one(p)
+            .await?
+            .try_get(0)
+    }
+
+    async fn get_batch_to_update(
+        &self,
+        masker_entity: &masker::Entity,
+        p: &Self::Pool,
+        _: &TableColumns,
+        b_size: i64,
+        last_key: Option<&[GeneratedValue]>,
+    ) -> Result<Vec<BatchRow>, sqlx::Error> {
+        let pk_len = masker_entity.get_pk().len();
+        let pk_names = masker_entity
+            .get_pk()
+            .iter()
+            .map(|c| quote_identifier(c.get_name()))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let columns = masker_entity
+            .get_entries()
+            .iter()
+            .map(|f| format!(", CAST({} AS TEXT)", quote_identifier(f.get_column_name())))
+            .collect::<String>();
+        let conds = match last_key {
+            Some(key) => vec![format!(
+                "({}) > ({})",
+                pk_names,
+                vec!["?"; key.len()].join(", ")
+            )],
+            None => vec![],
+        };
+        let query_str = format!(
+            "SELECT {}{} FROM {} {}ORDER BY {} LIMIT ?",
+            pk_names,
+            columns,
+            quote_table_name(&masker_entity.get_table_name()),
+            shared::where_clause(masker_entity, &conds),
+            pk_names
+        );
+        let mut query = sqlx::query(query_str.as_str());
+        for val in last_key.unwrap_or_default() {
+            query = bind_value(query, val);
+        }
+        query
+            .bind(b_size)
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| {
+                let key = (0..pk_len)
+                    .map(|i| decode_key_value(r, i))
+                    .collect::<Result<Vec<GeneratedValue>, sqlx::Error>>()?;
+                let originals = (pk_len..r.len())
+                    .map(|i| r.try_get::<Option<String>, _>(i))
+                    .collect::<Result<Vec<Option<String>>, sqlx::Error>>()?;
+                Ok((key, originals))
+            })
+            .collect()
+    }
+
+    // Describes every table of the DB for discovery. Position of the column in the primary key
+    // comes straight from table_info.
+    async fn get_catalog(&self, p: &Self::Pool) -> Result<Vec<TableCatalog>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT m.name, c.name, c.type, c.pk FROM sqlite_master AS m \
+            JOIN pragma_table_info(m.name) AS c \
+            WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite_%' ORDER BY m.name, c.cid;",
+        )
+        .fetch_all(p)
+        .await?
+        .iter()
+        .map(|r| Ok((r.try_get(0)?, r.try_get(1)?, r.try_get(2)?, r.try_get(3)?)))
+        .collect::<Result<Vec<(String, String, String, i64)>, sqlx::Error>>()?;
+        Ok(TableCatalog::from_rows(rows))
+    }
+
+    async fn get_column_values(
+        &self,
+        masker_entity: &masker::Entity,
+        column: &str,
+        p: &Self::Pool,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let column = quote_identifier(column);
+        let query_str = format!(
+            "SELECT DISTINCT CAST({} AS TEXT) FROM {} WHERE {} IS NOT NULL",
+            column,
+            quote_table_name(&masker_entity.get_table_name()),
+            column
+        );
+        sqlx::query(query_str.as_str())
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| r.try_get::<String, _>(0))
+            .collect()
+    }
+
+    async fn get_column_samples(
+        &self,
+        catalog: &TableCatalog,
+        p: &Self::Pool,
+        limit: i64,
+    ) -> Result<Vec<Vec<Option<String>>>, sqlx::Error> {
+        let columns = catalog.get_text_columns();
+        if columns.is_empty() {
+            return Ok(vec![]);
+        }
+        let query_str = format!(
+            "SELECT {} FROM {} LIMIT ?",
+            columns
+                .iter()
+                .map(|c| format!("CAST({} AS TEXT)", quote_identifier(c)))
+                .collect::<Vec<String>>()
+                .join(", "),
+            quote_identifier(catalog.get_table_name())
+        );
+        sqlx::query(query_str.as_str())
+            .bind(limit)
+            .fetch_all(p)
+            .await?
+            .iter()
+            .map(|r| {
+                (0..r.len())
+                    .map(|i| r.try_get::<Option<String>, _>(i))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Rows are written by a single UPDATE ... FROM joined with the list of masked rows
+    fn prepare_batch_query(
+        &self,
+        masker_entity: &masker::Entity,
+        table_columns: &TableColumns,
+        batch: &[BatchRow],
+    ) -> Result<Statement, DatabaseAdapterError> {
+        let args = shared::generate_batch_values(masker_entity, table_columns, batch)?;
+        let columns = shared::batch_columns(masker_entity);
+        let row = format!("({})", vec!["?"; columns.len()].join(", "));
+        let set = masker_entity
+            .get_entries()
+            .iter()
+            .map(|f| {
+                let column = quote_identifier(f.get_column_name());
+                if f.has_keep_rules() {
+                    let flag = quote_identifier(&shared::keep_flag_column(f.get_column_name()));
+                    format!("{column} = CASE WHEN v.{flag} THEN t.{column} ELSE v.{column} END")
+                } else {
+                    format!("{} = v.{}", column, column)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        let cond = masker_entity
+            .get_pk()
+            .iter()
+            .map(|c| {
+                let column = quote_identifier(c.get_name());
+                format!("t.{} = v.{}", column, column)
+            })
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        let query = format!(
+            "WITH v ({}) AS (VALUES {}) UPDATE {} AS t SET {} FROM v WHERE {}",
+            columns
+                .iter()
+                .map(|c| quote_identifier(c))
+                .collect::<Vec<String>>()
+                .join(", "),
+            vec![row; batch.len()].join(", "),
+            quote_table_name(&masker_entity.get_table_name()),
+            set,
+            cond
+        );
+
+        Ok((query, args))
+    }
+
+    async fn write_batch(
+        &self,
+        _: &masker::Entity,
+        p: &Self::Pool,
+        _: &TableColumns,
+        statements: &[Statement],
+    ) -> Result<(), DatabaseAdapterError> {
+        let mut tx = p
+            .begin()
+            .await
+            .map_err(DatabaseAdapterError::failed_query)?;
+        for (query_str, args) in statements.iter() {
+            let mut query = sqlx::query(query_str.as_str());
+            for val in args.iter() {
+                query = bind_value(query, val);
+            }
+            query
+                .execute(&mut *tx)
+                .await
+                .map_err(DatabaseAdapterError::failed_query)?;
+        }
+        tx.commit()
+            .await
+            .map_err(DatabaseAdapterError::failed_query)
+    }
+
+    fn script_block(
+        &self,
+        _: &masker::Entity,
+        _: &TableColumns,
+        statements: &[Statement],
+    ) -> Result<Vec<String>, DatabaseAdapterError> {
+        let inlined = statements
+            .iter()
+            .map(|(query_str, args)| script::inline_params(query_str, args, '"', literal))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok([String::from("BEGIN")]
+            .into_iter()
+            .chain(inlined)
+            .chain([String::from("COMMIT")])
+            .collect())
+    }
+}
+
+// Database the tests of the features built on top of the adapters mask, so that they run without
+// any DB server
+#[cfg(test)]
+pub mod test_db {
+    use super::*;
+
+    // In-memory database lives as long as the connection does, so the pool must never open a
+    // second one.
+    pub async fn get_test_conn() -> sqlx::SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE customers (customerNumber INTEGER PRIMARY KEY, contactFirstName TEXT NOT NULL);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        for (id, name) in [
+            (103, "Carine"),
+            (112, "Jean"),
+            (114, "Peter"),
+            (119, "Janine"),
+        ] {
+            sqlx::query("INSERT INTO customers VALUES (?, ?);")
+                .bind(id)
+                .bind(name)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query(
+            "CREATE TABLE payments (customerNumber INTEGER, checkNumber TEXT, comment TEXT NOT NULL, \
+            PRIMARY KEY (customerNumber, checkNumber));",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        for (id, check) in [(103, "HQ336336"), (103, "JM555205"), (112, "BO864823")] {
+            sqlx::query("INSERT INTO payments VALUES (?, ?, 'original');")
+                .bind(id)
+                .bind(check)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        pool
+    }
+
+    pub fn get_adapter() -> SQLiteAdapter {
+        get_file_adapter("test.db")
+    }
+
+    pub fn get_file_adapter(path: &str) -> SQLiteAdapter {
+        SQLiteAdapter {
+            connection_creds: SQLiteConnectionCredentials::new(path.to_string()),
+            scheduler: Scheduler::new(1, 1000, 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fake::{faker::name::raw::*, locales::EN, Fake};
+    use masker::{
+        generator::{Options, SimpleGenerator},
+        FieldKind,
+    };
+    use rand::rngs::StdRng;
+
+    use super::test_db::{get_adapter, get_test_conn};
+    use super::*;
+    use crate::database::adapter::DatabaseAdapter;
+    use crate::database::checkpoint::{Checkpoint, Progress};
+    use crate::database::error::DatabaseAdapterErrorKind;
+    use crate::database::schema;
+    use crate::database::script::Output;
+    use crate::masker::{Entity, Field, Masker, PkColumn, PkType};
+
+    fn get_generator() -> Box<SimpleGenerator> {
+        Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+            Ok(GeneratedValue::String(FirstName(EN).fake::<String>()))
+        }))
+    }
+
+    #[test]
+    fn adapter_generates_batch_query_from_masker_entity_with_int_pk() {
+        let adapter = get_adapter();
+        let fields: Vec<Field> = vec![Field::new(
+            "name".to_string(),
+            FieldKind::FirstName,
+            get_generator(),
+        )];
+        let entity = Entity::new(
+            "table".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            fields,
+        );
+        let rows = vec![
+            (vec![GeneratedValue::Number("123".to_string())], vec![None]),
+            (vec![GeneratedValue::Number("124".to_string())], vec![None]),
+        ];
+        let (query, args) = adapter
+            .prepare_batch_query(&entity, &TableColumns::new(), &rows)
+            .unwrap();
+        assert_eq!(
+            query,
+            r#"WITH v ("id", "name") AS (VALUES (?, ?), (?, ?)) UPDATE "table" AS t SET "name" = v."name" FROM v WHERE t."id" = v."id""#
+        );
+        assert_eq!(args.len(), 4);
+        assert_eq!(args[0], rows[0].0[0]);
+        assert_eq!(args[2], rows[1].0[0]);
+    }
+
+    #[test]
+    fn adapter_throws_error_if_entity_has_no_fields() {
+        let adapter = get_adapter();
+        let entity = Entity::new(
+            "table".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::String)],
+            vec![],
+        );
+        assert!(adapter
+            .prepare_batch_query(
+                &entity,
+                &TableColumns::new(),
+                &[(vec![GeneratedValue::String("123".to_string())], vec![])],
+            )
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn adapter_masks_table_values_in_sqlite() {
+        let adapter = get_adapter();
+        let fields: Vec<Field> = vec![Field::new(
+            "contactFirstName".to_string(),
+            FieldKind::FirstName,
+            get_generator(),
+        )];
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            fields,
+        );
+        let pool = get_test_conn().await;
+        let select = "SELECT contactFirstName FROM customers ORDER BY customerNumber ASC";
+        let before: Vec<String> = sqlx::query(select)
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect();
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let after: Vec<String> = sqlx::query(select)
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect();
+
+        assert_eq!(before.len(), after.len());
+        assert_ne!(before, after)
+    }
+
+    #[tokio::test]
+    async fn adapter_get_batch_to_update_returns_ids_from_sqlite() {
+        let adapter = get_adapter();
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![],
+        );
+        let pool = get_test_conn().await;
+
+        let res = adapter
+            .get_batch_to_update(
+                &entity,
+                &pool,
+                &TableColumns::new(),
+                2,
+                Some(&[GeneratedValue::Number("103".to_string())]),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            res,
+            vec![
+                (vec![GeneratedValue::Number("112".to_string())], vec![]),
+                (vec![GeneratedValue::Number("114".to_string())], vec![])
+            ]
+        )
+    }
+
+    #[tokio::test]
+    async fn adapter_reports_unknown_table_along_with_other_mismatches() {
+        let entity = Entity::new(
+            "missing".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            vec![],
+        );
+        let customers = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new(
+                "contactLastName".to_string(),
+                FieldKind::LastName,
+                get_generator(),
+            )],
+        );
+        let pool = get_test_conn().await;
+        let err =
+            shared::verify_entities(&get_adapter(), &Masker::new(vec![entity, customers]), &pool)
+                .await
+                .unwrap_err();
+
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::SchemaMismatch(m) if m == vec![
+                "missing: table not found",
+                "customers.contactLastName: column not found",
+            ]
+        ))
+    }
+
+    #[tokio::test]
+    async fn adapter_reports_every_column_mismatch_at_once() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let customers = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::String)],
+            vec![
+                Field::new(
+                    "contactFirstName".to_string(),
+                    FieldKind::FirstName,
+                    get_generator(),
+                ),
+                Field::new(
+                    "contactLastName".to_string(),
+                    FieldKind::LastName,
+                    get_generator(),
+                ),
+            ],
+        );
+        let payments = Entity::new(
+            "payments".to_string(),
+            vec![
+                PkColumn::new("customerNumber".to_string(), PkType::Int),
+                PkColumn::new("checkNumber".to_string(), PkType::String),
+            ],
+            vec![Field::new(
+                "customerNumber".to_string(),
+                FieldKind::Iban,
+                get_generator(),
+            )],
+        );
+        let err = shared::verify_entities(&adapter, &Masker::new(vec![customers, payments]), &pool)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::SchemaMismatch(m) if m == vec![
+                "customers.customerNumber: primary key of type string doesn't fit column of type INTEGER",
+                "customers.contactLastName: column not found",
+                "payments.customerNumber: values of Iban don't fit column of type INTEGER",
+            ]
+        ))
+    }
+
+    #[tokio::test]
+    async fn adapter_reads_primary_keys_left_out_of_config() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let payments = Entity::new("payments".to_string(), vec![], vec![]);
+        let columns = adapter.get_columns(&payments, &pool).await.unwrap();
+        let pk = schema::resolve_pk(&payments, &columns).unwrap();
+        assert_eq!(
+            pk.iter()
+                .map(|c| (c.get_name().as_str(), *c.get_type().unwrap()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("customerNumber", PkType::Int),
+                ("checkNumber", PkType::String)
+            ]
+        );
+
+        let customers = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new(
+                "contactFirstName".to_string(),
+                PkType::String,
+            )],
+            vec![],
+        );
+        let columns = adapter.get_columns(&customers, &pool).await.unwrap();
+        assert_eq!(
+            schema::resolve_pk(&customers, &columns).err().unwrap(),
+            "customers: configured key (contactFirstName) doesn't match primary key (customerNumber) of the table"
+        );
+    }
+
+    #[tokio::test]
+    async fn adapter_reads_columns_of_table_qualified_with_schema() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let customers = Entity::new("main.customers".to_string(), vec![], vec![]);
+        let columns = adapter.get_columns(&customers, &pool).await.unwrap();
+        assert_eq!(
+            columns
+                .get("customerNumber")
+                .map(|c| c.get_sql_type().as_str()),
+            Some("INTEGER")
+        );
+        assert_eq!(columns.len(), 2);
+
+        let missing = Entity::new("temp.customers".to_string(), vec![], vec![]);
+        assert!(adapter
+            .get_columns(&missing, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn adapter_masks_only_rows_matching_filter() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new(
+                "contactFirstName".to_string(),
+                FieldKind::Template,
+                Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                    Ok(GeneratedValue::String("masked".to_string()))
+                })),
+            )],
+        )
+        .with_filter("customerNumber < 110 OR contactFirstName = 'Janine'".to_string());
+
+        assert_eq!(adapter.get_total_size(&entity, &pool).await.unwrap(), 2);
+        let rows = adapter
+            .get_batch_to_update(
+                &entity,
+                &pool,
+                &TableColumns::new(),
+                1,
+                Some(&[GeneratedValue::Number("103".to_string())]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![(
+                vec![GeneratedValue::Number("119".to_string())],
+                vec![Some("Janine".to_string())]
+            )]
+        );
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let masked = sqlx::query("SELECT contactFirstName FROM customers ORDER BY customerNumber")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect::<Vec<String>>();
+        assert_eq!(masked, vec!["masked", "Jean", "Peter", "masked"]);
+    }
+
+    #[tokio::test]
+    async fn adapter_masks_table_with_composite_pk() {
+        let adapter = get_adapter();
+        let fields: Vec<Field> = vec![Field::new(
+            "comment".to_string(),
+            FieldKind::FirstName,
+            get_generator(),
+        )];
+        let entity = Entity::new(
+            "payments".to_string(),
+            vec![
+                PkColumn::new("customerNumber".to_string(), PkType::Int),
+                PkColumn::new("checkNumber".to_string(), PkType::String),
+            ],
+            fields,
+        );
+        let pool = get_test_conn().await;
+
+        let original = vec![Some("original".to_string())];
+        let rows = adapter
+            .get_batch_to_update(&entity, &pool, &TableColumns::new(), 2, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    vec![
+                        GeneratedValue::Number("103".to_string()),
+                        GeneratedValue::String("HQ336336".to_string())
+                    ],
+                    original.clone()
+                ),
+                (
+                    vec![
+                        GeneratedValue::Number("103".to_string()),
+                        GeneratedValue::String("JM555205".to_string())
+                    ],
+                    original.clone()
+                )
+            ]
+        );
+
+        let (last_key, _) = rows.last().unwrap();
+        let rows = adapter
+            .get_batch_to_update(&entity, &pool, &TableColumns::new(), 2, Some(last_key))
+            .await
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![(
+                vec![
+                    GeneratedValue::Number("112".to_string()),
+                    GeneratedValue::String("BO864823".to_string())
+                ],
+                original
+            )]
+        );
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let untouched = sqlx::query("SELECT COUNT(*) FROM payments WHERE comment = 'original'")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get::<i64, _>(0);
+        assert_eq!(untouched, 0)
+    }
+
+    #[tokio::test]
+    async fn adapter_masks_table_with_binary_and_wide_int_keys() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        sqlx::query("CREATE TABLE devices (id BLOB PRIMARY KEY, owner TEXT NOT NULL);")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE events (id INTEGER PRIMARY KEY, owner TEXT NOT NULL);")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let uuid: Vec<u8> = (0..16).collect();
+        sqlx::query("INSERT INTO devices VALUES (?, 'original');")
+            .bind(&uuid)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO events VALUES (5000000000, 'original');")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        for (t_name, pk_type, expected_key) in [
+            (
+                "devices",
+                PkType::Binary,
+                GeneratedValue::Bytes(uuid.clone()),
+            ),
+            (
+                "events",
+                PkType::UnsignedBigInt,
+                GeneratedValue::Number("5000000000".to_string()),
+            ),
+        ] {
+            let entity = Entity::new(
+                t_name.to_string(),
+                vec![PkColumn::new("id".to_string(), pk_type)],
+                vec![Field::new(
+                    "owner".to_string(),
+                    FieldKind::FirstName,
+                    get_generator(),
+                )],
+            );
+            let rows = adapter
+                .get_batch_to_update(&entity, &pool, &TableColumns::new(), 10, None)
+                .await
+                .unwrap();
+            assert_eq!(
+                rows,
+                vec![(vec![expected_key], vec![Some("original".to_string())])]
+            );
+
+            shared::mask_table(
+                &adapter,
+                &entity,
+                &pool,
+                &Checkpoint::new(None),
+                Output::Database,
+            )
+            .await
+            .unwrap();
+            let owner = sqlx::query(format!("SELECT owner FROM {t_name}").as_str())
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .get::<String, _>(0);
+            assert_ne!(owner, "original");
+        }
+    }
+
+    #[tokio::test]
+    async fn adapter_masks_table_across_several_batches() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        sqlx::query("CREATE TABLE contacts (id INTEGER PRIMARY KEY, name TEXT NOT NULL);")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "WITH RECURSIVE seq(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM seq WHERE n < 2500) \
+            INSERT INTO contacts SELECT n, 'original' FROM seq;",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let entity = Entity::new(
+            "contacts".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            vec![Field::new(
+                "name".to_string(),
+                FieldKind::FirstName,
+                get_generator(),
+            )],
+        );
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let untouched = sqlx::query("SELECT COUNT(*) FROM contacts WHERE name = 'original'")
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+            .get::<i64, _>(0);
+        assert_eq!(untouched, 0)
+    }
+
+    // SQLite adapter that records how many batches are written at the same time and what
+    // progress the checkpoint holds whenever a batch is written. First batch is written last, so
+    // that the batches after it are written before it is.
+    struct TracedAdapter<'a> {
+        inner: SQLiteAdapter,
+        scheduler: Scheduler,
+        checkpoint: &'a Checkpoint,
+        calls: std::sync::atomic::AtomicUsize,
+        in_flight: std::sync::atomic::AtomicUsize,
+        max_in_flight: std::sync::atomic::AtomicUsize,
+        written: std::sync::Mutex<Vec<(usize, Option<Progress>)>>,
+    }
+
+    #[async_trait]
+    impl Dialect for TracedAdapter<'_> {
+        type Pool = sqlx::SqlitePool;
+        const MAX_PARAMS: usize = SQLiteAdapter::MAX_PARAMS;
+
+        fn get_scheduler(&self) -> &Scheduler {
+            &self.scheduler
+        }
+        fn is_valid_identifier(ident: &str) -> bool {
+            SQLiteAdapter::is_valid_identifier(ident)
+        }
+        async fn connect(&self, read_only: bool) -> Result<Self::Pool, DatabaseAdapterError> {
+            self.inner.connect(read_only).await
+        }
+        async fn get_columns(
+            &self,
+            masker_entity: &Entity,
+            p: &Self::Pool,
+        ) -> Result<TableColumns, sqlx::Error> {
+            self.inner.get_columns(masker_entity, p).await
+        }
+        async fn get_total_size(
+            &self,
+            masker_entity: &Entity,
+            p: &Self::Pool,
+        ) -> Result<i64, sqlx::Error> {
+            self.inner.get_total_size(masker_entity, p).await
+        }
+        async fn get_batch_to_update(
+            &self,
+            masker_entity: &Entity,
+            p: &Self::Pool,
+            table_columns: &TableColumns,
+            b_size: i64,
+            last_key: Option<&[GeneratedValue]>,
+        ) -> Result<Vec<BatchRow>, sqlx::Error> {
+            self.inner
+                .get_batch_to_update(masker_entity, p, table_columns, b_size, last_key)
+                .await
+        }
+        async fn get_catalog(&self, p: &Self::Pool) -> Result<Vec<TableCatalog>, sqlx::Error> {
+            self.inner.get_catalog(p).await
+        }
+        async fn get_column_values(
+            &self,
+            masker_entity: &Entity,
+            column: &str,
+            p: &Self::Pool,
+        ) -> Result<Vec<String>, sqlx::Error> {
+            self.inner.get_column_values(masker_entity, column, p).await
+        }
+        async fn get_column_samples(
+            &self,
+            catalog: &TableCatalog,
+            p: &Self::Pool,
+            limit: i64,
+        ) -> Result<Vec<Vec<Option<String>>>, sqlx::Error> {
+            self.inner.get_column_samples(catalog, p, limit).await
+        }
+        fn prepare_batch_query(
+            &self,
+            masker_entity: &Entity,
+            table_columns: &TableColumns,
+            batch: &[BatchRow],
+        ) -> Result<Statement, DatabaseAdapterError> {
+            self.inner
+                .prepare_batch_query(masker_entity, table_columns, batch)
+        }
+        async fn write_batch(
+            &self,
+            masker_entity: &Entity,
+            p: &Self::Pool,
+            table_columns: &TableColumns,
+            statements: &[Statement],
+        ) -> Result<(), DatabaseAdapterError> {
+            use std::sync::atomic::Ordering;
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            if call == 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+            let res = self
+                .inner
+                .write_batch(masker_entity, p, table_columns, statements)
+                .await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            let progress = self.checkpoint.get_progress(masker_entity)?;
+            self.written.lock().unwrap().push((call, progress));
+            res
+        }
+        fn script_block(
+            &self,
+            masker_entity: &Entity,
+            table_columns: &TableColumns,
+            statements: &[Statement],
+        ) -> Result<Vec<String>, DatabaseAdapterError> {
+            self.inner
+                .script_block(masker_entity, table_columns, statements)
+        }
+    }
+
+    #[tokio::test]
+    async fn adapter_writes_batches_of_table_concurrently() {
+        let checkpoint = Checkpoint::new(None);
+        let adapter = TracedAdapter {
+            inner: get_adapter(),
+            scheduler: Scheduler::new(3, 1, 3),
+            checkpoint: &checkpoint,
+            calls: Default::default(),
+            in_flight: Default::default(),
+            max_in_flight: Default::default(),
+            written: Default::default(),
+        };
+        let pool = get_test_conn().await;
+        let fields: Vec<Field> = vec![Field::new(
+            "contactFirstName".to_string(),
+            FieldKind::Template,
+            Box::new(SimpleGenerator::new(|_: &Options, _: &mut StdRng| {
+                Ok(GeneratedValue::String("masked".to_string()))
+            })),
+        )];
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            fields,
+        );
+
+        shared::mask_table(&adapter, &entity, &pool, &checkpoint, Output::Database)
+            .await
+            .unwrap();
+        let masked =
+            sqlx::query("SELECT COUNT(*) FROM customers WHERE contactFirstName = 'masked'")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .get::<i64, _>(0);
+        assert_eq!(masked, 4);
+        // Batches after the first one are written while it's still being written
+        assert!(
+            adapter
+                .max_in_flight
+                .load(std::sync::atomic::Ordering::SeqCst)
+                >= 2
+        );
+        // Progress doesn't move past the first batch until it's written, even though the two
+        // batches after it are written before it
+        let key = |k: &str| {
+            Some(Progress::MaskedUpTo(vec![GeneratedValue::Number(
+                k.to_string(),
+            )]))
+        };
+        assert_eq!(
+            *adapter.written.lock().unwrap(),
+            vec![(1, None), (2, None), (0, None), (3, key("114"))]
+        );
+        assert_eq!(
+            checkpoint.get_progress(&entity).unwrap(),
+            Some(Progress::Done)
+        );
+    }
+
+    #[tokio::test]
+    async fn adapter_writes_values_with_special_characters_as_is() {
+        let adapter = get_adapter();
+        let value = r#"O'Neil "Jr" \ ; --"#;
+        let fields: Vec<Field> = vec![Field::new(
+            "contactFirstName".to_string(),
+            FieldKind::Template,
+            Box::new(SimpleGenerator::new(move |_: &Options, _: &mut StdRng| {
+                Ok(GeneratedValue::String(value.to_string()))
+            })),
+        )];
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            fields,
+        );
+        let pool = get_test_conn().await;
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let written: Vec<String> = sqlx::query("SELECT contactFirstName FROM customers")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect();
+        assert!(written.iter().all(|v| v == value), "{written:?}")
+    }
+
+    #[tokio::test]
+    async fn adapter_masks_table_with_reserved_and_mixed_case_names() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        sqlx::query(
+            r#"CREATE TABLE "Order" ("key" INTEGER PRIMARY KEY, "group" TEXT, "Group Name" TEXT);"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        for id in 1..=3 {
+            sqlx::query(r#"INSERT INTO "Order" VALUES (?, 'original', 'original');"#)
+                .bind(id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let fields: Vec<Field> = vec![
+            Field::new("group".to_string(), FieldKind::FirstName, get_generator()),
+            Field::new(
+                "Group Name".to_string(),
+                FieldKind::FirstName,
+                get_generator(),
+            ),
+        ];
+        let entity = Entity::new(
+            "Order".to_string(),
+            vec![PkColumn::new("key".to_string(), PkType::Int)],
+            fields,
+        );
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let untouched = sqlx::query(
+            r#"SELECT COUNT(*) FROM "Order" WHERE "group" = 'original' OR "Group Name" = 'original'"#,
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get::<i64, _>(0);
+        assert_eq!(untouched, 0)
+    }
+
+    #[tokio::test]
+    async fn kept_values_are_never_written_back() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        sqlx::query(
+            "UPDATE customers SET contactFirstName = X'436172696E65' WHERE customerNumber = 103",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let yaml = serde_yaml::from_str(
+            "name: contactFirstName
+kind: Template
+template: masked
+keep_if:
+  customerNumber: ^(103|112)$",
+        )
+        .unwrap();
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new_from_yaml(&yaml, masker::generator::Locale::default()).unwrap()],
+        );
+
+        // Kept value longer than the column is neither truncated nor bound at all
+        let columns = TableColumns::from([(
+            "contactFirstName".to_string(),
+            ColumnInfo::new("varchar(3)".to_string(), Some(3), Charset::Utf8),
+        )]);
+        let row = (
+            vec![GeneratedValue::Number("112".to_string())],
+            vec![Some("Jean".to_string())],
+        );
+        assert_eq!(
+            shared::generate_batch_values(&entity, &columns, &[row]).unwrap(),
+            vec![
+                GeneratedValue::Number("112".to_string()),
+                GeneratedValue::Null,
+                GeneratedValue::Number("1".to_string())
+            ]
+        );
+        // Binary value that was read as text is left as it is
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let kept = sqlx::query(
+            "SELECT typeof(contactFirstName), contactFirstName FROM customers WHERE customerNumber = 103",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(kept.get::<String, _>(0), "blob");
+        assert_eq!(kept.get::<Vec<u8>, _>(1), b"Carine");
+    }
+
+    #[tokio::test]
+    async fn adapter_rejects_identifiers_with_nul() {
+        let adapter = get_adapter();
+        let mut masker = Masker::new(vec![Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new(
+                "contact\0FirstName".to_string(),
+                FieldKind::FirstName,
+                get_generator(),
+            )],
+        )]);
+        // Identifiers are checked before the connection is opened, so the missing test.db file
+        // doesn't matter here.
+        let err = adapter
+            .apply_mask(&mut masker, &Checkpoint::new(None))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DatabaseAdapterErrorKind::InvalidIdentifier(_)
+        ))
+    }
+}
@@ -1,10 +1,18 @@
 use std::process::exit;
 
+mod cli;
 mod database;
 mod masker;
 
 #[tokio::main]
 async fn main() {
+    let args = match cli::Args::parse(std::env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => {
+            println!("{e}\n{}", cli::USAGE);
+            exit(1);
+        }
+    };
     let f = std::fs::File::open("schema.yaml").expect("couldn't read config file");
     let yaml = match serde_yaml::from_reader(f) {
         Ok(y) => y,
@@ -13,22 +21,80 @@ async fn main() {
             exit(1);
         }
     };
-    let masker = match masker::Masker::new_from_yaml(&yaml) {
+    let db = match database::new_db_adapter_from_yaml(&yaml) {
+        Ok(db) => db,
+        Err(e) => {
+            println!("couldn't create DB adapter instance: {e}");
+            exit(1);
+        }
+    };
+
+    // Discovery drafts the schemas, so it doesn't need them to be in the config yet
+    if let Some(path) = args.discover {
+        let discovered = match db.discover().await {
+            Ok(d) => d,
+            Err(e) => {
+                println!("couldn't discover personal data in the DB: {e}");
+                exit(1);
+            }
+        };
+        discovered.iter().for_each(|d| println!("{d}"));
+        let draft = database::draft_schema(&yaml["db"], &discovered);
+        if let Err(e) = std::fs::write(&path, draft) {
+            println!("couldn't write the draft config to {}: {e}", path.display());
+            exit(1);
+        }
+        return;
+    }
+
+    let mut masker = match masker::Masker::new_from_yaml(&yaml) {
         Ok(m) => m,
         Err(e) => {
             println!("couldn't create masker entity: {e}");
             exit(1);
         }
     };
-    let db = match database::new_db_adapter_from_yaml(&yaml) {
-        Ok(db) => db,
+    if let Some(seed) = args.seed {
+        masker.set_seed(seed);
+    }
+    if args.dry_run {
+        match db.preview_mask(&mut masker).await {
+            Ok(previews) => previews.iter().for_each(|p| println!("{p}")),
+            Err(e) => {
+                println!("couldn't preview masking of the schema: {e}");
+                exit(1);
+            }
+        };
+        return;
+    }
+
+    if let Some(path) = args.export {
+        let res = match database::ScriptWriter::create(&path) {
+            Ok(script) => db.export_mask(&mut masker, &script).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            println!("couldn't export masking of the schema: {e}");
+            exit(1);
+        }
+        return;
+    }
+
+    let checkpoint = match database::Checkpoint::new_from_yaml(&yaml["db"]) {
+        Ok(c) => c,
         Err(e) => {
-            println!("couldn't create DB adapter instance: {e}");
+            println!("couldn't read checkpoint config: {e}");
             exit(1);
         }
     };
+    if args.resume {
+        if let Err(e) = checkpoint.resume() {
+            println!("{e}");
+            exit(1);
+        }
+    }
 
-    match db.apply_mask(&masker).await {
+    match db.apply_mask(&mut masker, &checkpoint).await {
         Ok(_) => (),
         Err(e) => {
             println!("couldn't mask the schema correctly: {e}");
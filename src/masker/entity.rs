@@ -1,43 +1,39 @@
-use crate::masker::Field;
+use crate::masker::{
+    generator::{GeneratedValue, Locale},
+    Field, SeedKey,
+};
+use rand::{rngs::StdRng, thread_rng, SeedableRng};
 use std::{borrow::Borrow, fmt::Display};
 
 use super::error::{ConfigParseError, ConfigParseErrorKind};
 
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PkType {
     Int,
+    UnsignedBigInt,
     String,
+    Uuid,
+    Binary,
 }
 
-pub struct Entity {
+// Describes a single column of the primary key. Tables with composite primary keys have
+// several of them, in the same order as they are listed in config. Type that is left out of
+// config is read from the DB before masking.
+pub struct PkColumn {
     name: String,
-    pk_name: String,
-    pk_type: PkType,
-    entries: Vec<Field>,
+    pk_type: Option<PkType>,
 }
 
-impl Entity {
-    pub fn new(name: String, pk_name: String, pk_type: PkType, entries: Vec<Field>) -> Self {
+impl PkColumn {
+    pub fn new(name: String, pk_type: PkType) -> Self {
         Self {
             name,
-            pk_name,
-            pk_type,
-            entries,
+            pk_type: Some(pk_type),
         }
     }
 
     pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
-        let field = String::from("table");
-        let s_name = match yaml[field.as_str()].as_str() {
-            Some(s) => String::from(s),
-            None => {
-                return Err(ConfigParseError {
-                    kind: ConfigParseErrorKind::MissingField,
-                    field,
-                })
-            }
-        };
-
-        let s_pk_name = match yaml["pk"]["name"].as_str() {
+        let name = match yaml["name"].as_str() {
             Some(s) => String::from(s),
             None => {
                 return Err(ConfigParseError {
@@ -47,21 +43,99 @@ impl Entity {
             }
         };
 
-        let s_pk_type = match yaml["pk"]["type"].as_str() {
-            Some(s) => match s {
+        let pk_type = match &yaml["type"] {
+            serde_yaml::Value::Null => None,
+            serde_yaml::Value::String(s) => Some(match s.as_str() {
                 "int" => PkType::Int,
+                "unsigned_bigint" => PkType::UnsignedBigInt,
                 "string" => PkType::String,
+                "uuid" => PkType::Uuid,
+                "binary" => PkType::Binary,
                 other => {
                     return Err(ConfigParseError {
                         kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from(other)),
                         field: String::from("pk.type"),
                     })
                 }
-            },
+            }),
+            _ => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                    field: String::from("pk.type"),
+                })
+            }
+        };
+
+        Ok(Self { name, pk_type })
+    }
+
+    pub fn get_name(&self) -> &String {
+        self.name.borrow()
+    }
+    // Type is None if it's left out of config and hasn't been read from the DB yet
+    pub fn get_type(&self) -> Option<&PkType> {
+        self.pk_type.as_ref()
+    }
+}
+
+pub struct Entity {
+    name: String,
+    pk: Vec<PkColumn>,
+    entries: Vec<Field>,
+    filter: Option<String>,
+    seed_key: Option<SeedKey>,
+    // Position among the entities masking the same table, counted from 1
+    ordinal: usize,
+}
+
+impl Entity {
+    pub fn new(name: String, pk: Vec<PkColumn>, entries: Vec<Field>) -> Self {
+        Self {
+            name,
+            pk,
+            entries,
+            filter: None,
+            seed_key: None,
+            ordinal: 1,
+        }
+    }
+
+    // Only the rows matching the filter get masked, the rest of the table is left untouched
+    pub fn with_filter(mut self, filter: String) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    // Fields are generated in the given locale, unless they have their own
+    pub fn new_from_yaml(
+        yaml: &serde_yaml::Value,
+        locale: Locale,
+    ) -> Result<Self, ConfigParseError> {
+        let field = String::from("table");
+        let s_name = match yaml[field.as_str()].as_str() {
+            Some(s) => String::from(s),
             None => {
                 return Err(ConfigParseError {
                     kind: ConfigParseErrorKind::MissingField,
-                    field: String::from("pk.type"),
+                    field,
+                })
+            }
+        };
+
+        // pk is either a single column or a list of columns for tables with composite keys. When
+        // it's left out, the primary key of the table is read from the DB before masking.
+        let field = "pk";
+        let s_pk: Vec<PkColumn> = match &yaml[field] {
+            serde_yaml::Value::Null => vec![],
+            serde_yaml::Value::Sequence(seq) if !seq.is_empty() => seq
+                .iter()
+                .map(PkColumn::new_from_yaml)
+                .collect::<Result<Vec<PkColumn>, ConfigParseError>>()?,
+            serde_yaml::Value::Mapping(_) => vec![PkColumn::new_from_yaml(&yaml[field])?],
+            _ => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::MissingField,
+                    field: String::from(field),
                 })
             }
         };
@@ -70,7 +144,7 @@ impl Entity {
         let mut s_fields: Vec<Field> = match yaml[field].as_sequence() {
             Some(seq) => seq
                 .iter()
-                .map(Field::new_from_yaml)
+                .map(|f| Field::new_from_yaml(f, locale))
                 .collect::<Result<Vec<Field>, ConfigParseError>>()?,
             None => {
                 return Err(ConfigParseError {
@@ -83,26 +157,82 @@ impl Entity {
         // simple ones are done due to the dependencies between them.
         s_fields.sort();
 
-        Ok(Entity::new(s_name, s_pk_name, s_pk_type, s_fields))
+        // Row filter is an SQL predicate that is put into the WHERE clause of the queries as it is
+        let field = "where";
+        let entity = Entity::new(s_name, s_pk, s_fields);
+        match &yaml[field] {
+            serde_yaml::Value::Null => Ok(entity),
+            serde_yaml::Value::String(s) if s.trim().is_empty() => Err(ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(s.clone()),
+                field: String::from(field),
+            }),
+            serde_yaml::Value::String(s) => Ok(entity.with_filter(s.clone())),
+            _ => Err(ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldType,
+                field: String::from(field),
+            }),
+        }
     }
 
     pub fn get_table_name(&self) -> String {
         self.name.to_string()
     }
-    pub fn get_pk_name(&self) -> &String {
-        self.pk_name.borrow()
+    pub fn get_pk(&self) -> &Vec<PkColumn> {
+        self.pk.borrow()
     }
-    pub fn get_pk_type(&self) -> &PkType {
-        self.pk_type.borrow()
+    pub fn set_pk(&mut self, pk: Vec<PkColumn>) {
+        self.pk = pk;
     }
     pub fn get_entries(&self) -> &Vec<Field> {
         self.entries.borrow()
     }
+    pub fn get_entries_mut(&mut self) -> &mut Vec<Field> {
+        &mut self.entries
+    }
+    pub fn get_filter(&self) -> Option<&String> {
+        self.filter.as_ref()
+    }
+    pub fn get_ordinal(&self) -> usize {
+        self.ordinal
+    }
+    pub fn set_ordinal(&mut self, ordinal: usize) {
+        self.ordinal = ordinal;
+    }
+    pub fn set_seed_key(&mut self, seed_key: SeedKey) {
+        self.seed_key = Some(seed_key);
+    }
+
+    // With the global seed set, every row gets its own RNG derived from the table name and the
+    // key, so that the output only depends on the seed and the data, no matter how the rows are
+    // split into batches and in which order the tables are masked. Without the seed, the RNG is
+    // seeded from the thread RNG, which only reads the OS entropy once per thread rather than once
+    // per row.
+    pub fn rng_for_row(&self, key: &[GeneratedValue]) -> StdRng {
+        match &self.seed_key {
+            Some(seed_key) => {
+                let row = std::iter::once(self.name.clone())
+                    .chain(key.iter().map(|v| v.to_string()))
+                    .collect::<Vec<String>>()
+                    .join("\0");
+                seed_key.rng_for(&row)
+            }
+            None => StdRng::from_rng(thread_rng()).expect("thread RNG never fails"),
+        }
+    }
 }
 
 impl Display for Entity {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "name: {}, pk_name: {}", self.name, self.pk_name)?;
+        writeln!(
+            f,
+            "name: {}, pk: {}",
+            self.name,
+            self.pk
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )?;
         writeln!(f, "entries: \n")?;
         for entry in &self.entries {
             writeln!(f, " {}", entry)?;
@@ -110,3 +240,433 @@ impl Display for Entity {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use fake::{faker::name::raw::*, locales::EN, Fake};
+    use rand::{rngs::StdRng, SeedableRng};
+    use sqlx::Row;
+
+    use super::{Entity, PkType};
+    use crate::database::shared;
+    use crate::database::test_db::{get_adapter, get_test_conn};
+    use crate::database::{Checkpoint, Output};
+    use crate::masker::error::{ConfigParseError, ConfigParseErrorKind};
+    use crate::masker::generator::{
+        GeneratedValue, GeneratorErrorKind, Locale, Options, SimpleGenerator,
+    };
+    use crate::masker::{Field, FieldKind, OverflowPolicy, PkColumn, SeedKey};
+
+    #[test]
+    fn parses_single_column_pk() {
+        let yaml = serde_yaml::from_str(
+            "table: customers
+pk:
+  name: customerNumber
+  type: int
+fields:
+  - name: contactFirstName
+    kind: FirstName",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        assert_eq!(entity.get_pk().len(), 1);
+        assert_eq!(entity.get_pk()[0].get_name(), "customerNumber");
+        assert!(matches!(entity.get_pk()[0].get_type(), Some(PkType::Int)));
+    }
+
+    #[test]
+    fn parses_composite_pk() {
+        let yaml = serde_yaml::from_str(
+            "table: payments
+pk:
+  - name: customerNumber
+    type: int
+  - name: checkNumber
+    type: string
+fields:
+  - name: checkNumber
+    kind: Template
+    template: \"%{LLdddddd}\"",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let pk = entity.get_pk();
+        assert_eq!(pk.len(), 2);
+        assert_eq!(pk[0].get_name(), "customerNumber");
+        assert!(matches!(pk[0].get_type(), Some(PkType::Int)));
+        assert_eq!(pk[1].get_name(), "checkNumber");
+        assert!(matches!(pk[1].get_type(), Some(PkType::String)));
+    }
+
+    #[test]
+    fn parses_wide_and_binary_pk_types() {
+        let yaml = serde_yaml::from_str(
+            "table: devices
+pk:
+  - name: id
+    type: uuid
+  - name: serial
+    type: unsigned_bigint
+  - name: fingerprint
+    type: binary
+fields: []",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let pk = entity.get_pk();
+        assert!(matches!(pk[0].get_type(), Some(PkType::Uuid)));
+        assert!(matches!(pk[1].get_type(), Some(PkType::UnsignedBigInt)));
+        assert!(matches!(pk[2].get_type(), Some(PkType::Binary)));
+    }
+
+    #[test]
+    fn leaves_pk_to_the_db_when_not_configured() {
+        let yaml = serde_yaml::from_str(
+            "table: payments
+fields: []",
+        )
+        .unwrap();
+        assert!(Entity::new_from_yaml(&yaml, Locale::default())
+            .unwrap()
+            .get_pk()
+            .is_empty());
+
+        let yaml = serde_yaml::from_str(
+            "table: payments
+pk:
+  - name: customerNumber
+  - name: checkNumber
+    type: string
+fields: []",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let pk = entity.get_pk();
+        assert_eq!(pk[0].get_name(), "customerNumber");
+        assert_eq!(pk[0].get_type(), None);
+        assert_eq!(pk[1].get_type(), Some(&PkType::String));
+    }
+
+    #[test]
+    fn fails_on_empty_pk_list() {
+        let yaml = serde_yaml::from_str(
+            "table: payments
+pk: []
+fields: []",
+        )
+        .unwrap();
+        let err = Entity::new_from_yaml(&yaml, Locale::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            ConfigParseError {
+                kind: ConfigParseErrorKind::MissingField,
+                field: String::from("pk"),
+            }
+        )
+    }
+
+    #[test]
+    fn parses_deterministic_fields() {
+        let yaml = serde_yaml::from_str(
+            "table: customers
+fields:
+  - name: email
+    kind: Template
+    template: \"%{llllll}@example.com\"
+    deterministic: true
+  - name: contactFirstName
+    kind: FirstName",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let fields = entity.get_entries();
+        assert!(!fields[0].is_deterministic());
+        assert!(fields[1].is_deterministic());
+
+        let yaml = serde_yaml::from_str(
+            "table: customers
+fields:
+  - name: contactFirstName
+    kind: FirstName
+    deterministic: always",
+        )
+        .unwrap();
+        assert_eq!(
+            Entity::new_from_yaml(&yaml, Locale::default())
+                .err()
+                .unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldType,
+                field: String::from("deterministic"),
+            }
+        )
+    }
+
+    #[test]
+    fn parses_overflow_policy_of_fields() {
+        let yaml = serde_yaml::from_str(
+            "table: customers
+pk:
+  name: customerNumber
+  type: int
+fields:
+  - name: city
+    kind: CityName
+    on_overflow: reject
+  - name: contactFirstName
+    kind: FirstName",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let fields = entity.get_entries();
+        assert_eq!(fields[0].get_overflow_policy(), OverflowPolicy::Reject);
+        assert_eq!(fields[1].get_overflow_policy(), OverflowPolicy::Truncate);
+
+        let yaml = serde_yaml::from_str(
+            "table: customers
+pk:
+  name: customerNumber
+  type: int
+fields:
+  - name: city
+    kind: CityName
+    on_overflow: wrap",
+        )
+        .unwrap();
+        assert_eq!(
+            Entity::new_from_yaml(&yaml, Locale::default())
+                .err()
+                .unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from("wrap")),
+                field: String::from("on_overflow"),
+            }
+        )
+    }
+
+    #[test]
+    fn keeps_nulls_of_fields_preserving_them() {
+        let yaml = serde_yaml::from_str(
+            "table: customers
+fields:
+  - name: addressLine2
+    kind: CityName
+    preserve_null: true
+  - name: state
+    kind: StateName
+    null_probability: 1",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let fields = entity.get_entries();
+        let mut rng = StdRng::seed_from_u64(1);
+        let generate = |original: Option<&str>, rng: &mut StdRng| {
+            fields[0]
+                .generate(&HashMap::new(), original, rng, None)
+                .unwrap()
+        };
+        assert_eq!(generate(None, &mut rng), GeneratedValue::Null);
+        assert_eq!(
+            generate(Some(""), &mut rng),
+            GeneratedValue::String(String::new())
+        );
+        assert_ne!(generate(Some("Level 3"), &mut rng), GeneratedValue::Null);
+        assert_eq!(
+            fields[1]
+                .generate(&HashMap::new(), Some("CA"), &mut rng, None)
+                .unwrap(),
+            GeneratedValue::Null
+        );
+
+        let yaml = serde_yaml::from_str(
+            "table: customers
+fields:
+  - name: state
+    kind: StateName
+    null_probability: 1.5",
+        )
+        .unwrap();
+        assert_eq!(
+            Entity::new_from_yaml(&yaml, Locale::default())
+                .err()
+                .unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from("1.5")),
+                field: String::from("null_probability"),
+            }
+        )
+    }
+
+    #[test]
+    fn parses_row_filter() {
+        let yaml = serde_yaml::from_str(
+            "table: customers
+where: is_internal = 0
+fields:
+  - name: contactFirstName
+    kind: FirstName",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        assert_eq!(entity.get_filter().unwrap(), "is_internal = 0");
+
+        let yaml = serde_yaml::from_str(
+            "table: customers
+where: 1
+fields:
+  - name: contactFirstName
+    kind: FirstName",
+        )
+        .unwrap();
+        assert_eq!(
+            Entity::new_from_yaml(&yaml, Locale::default())
+                .err()
+                .unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldType,
+                field: String::from("where"),
+            }
+        )
+    }
+
+    #[test]
+    fn keeps_values_allowed_by_fields() {
+        let yaml = serde_yaml::from_str(
+            "table: customers
+fields:
+  - name: email
+    kind: Template
+    template: \"%{llllll}@example.com\"
+    keep_if: \"@ourcompany\\\\.com$\"",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let field = &entity.get_entries()[0];
+        let kept = |original: Option<&str>| field.kept_value(&HashMap::new(), original).unwrap();
+        assert_eq!(
+            kept(Some("qa@ourcompany.com")),
+            Some(GeneratedValue::String("qa@ourcompany.com".to_string()))
+        );
+        assert_eq!(kept(Some("jd@ourcompany.com.evil")), None);
+
+        let yaml = serde_yaml::from_str(
+            "table: customers
+fields:
+  - name: email
+    kind: FirstName
+    keep_if: \"[a-z\"",
+        )
+        .unwrap();
+        assert_eq!(
+            Entity::new_from_yaml(&yaml, Locale::default())
+                .err()
+                .unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from("[a-z")),
+                field: String::from("keep_if"),
+            }
+        )
+    }
+
+    #[test]
+    fn unique_fields_never_repeat_values() {
+        let yaml = serde_yaml::from_str(
+            "table: customers
+fields:
+  - name: pin
+    kind: Template
+    template: \"%{d}\"
+    unique: true",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let field = &entity.get_entries()[0];
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut values = vec![];
+        let err = loop {
+            match field.generate(&HashMap::new(), Some("0"), &mut rng, None) {
+                Ok(val) if values.len() <= 10 => values.push(val.to_string()),
+                Ok(val) => panic!("single digit {val} is generated after {values:?}"),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(err.kind, GeneratorErrorKind::UniqueValuesExhausted(100));
+        let count = values.len();
+        values.sort();
+        values.dedup();
+        assert_eq!(values.len(), count);
+    }
+
+    #[test]
+    fn unique_fields_never_issue_reserved_values() {
+        let yaml = serde_yaml::from_str(
+            "table: customers
+fields:
+  - name: pin
+    kind: Template
+    template: \"%{d}\"
+    unique: true",
+        )
+        .unwrap();
+        let entity = Entity::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let field = &entity.get_entries()[0];
+        field.reserve_values((1..9).map(|d| d.to_string()).collect(), None);
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(
+            field.generate(&HashMap::new(), Some("0"), &mut rng, None),
+            Ok(GeneratedValue::String(String::from("0")))
+        );
+        assert_eq!(
+            field
+                .generate(&HashMap::new(), Some("0"), &mut rng, None)
+                .unwrap_err()
+                .kind,
+            GeneratorErrorKind::UniqueValuesExhausted(100)
+        );
+    }
+
+    #[tokio::test]
+    async fn entities_are_masked_the_same_way_with_the_same_seed() {
+        let adapter = get_adapter();
+        let mut masked = vec![];
+        for _ in 0..2 {
+            let pool = get_test_conn().await;
+            let mut entity = Entity::new(
+                "customers".to_string(),
+                vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+                vec![Field::new(
+                    "contactFirstName".to_string(),
+                    FieldKind::FirstName,
+                    Box::new(SimpleGenerator::new(|_: &Options, rng: &mut StdRng| {
+                        Ok(GeneratedValue::String(FirstName(EN).fake_with_rng(rng)))
+                    })),
+                )],
+            );
+            entity.set_seed_key(SeedKey::new("42"));
+            shared::mask_table(
+                &adapter,
+                &entity,
+                &pool,
+                &Checkpoint::new(None),
+                Output::Database,
+            )
+            .await
+            .unwrap();
+            masked.push(
+                sqlx::query("SELECT contactFirstName FROM customers ORDER BY customerNumber")
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|r| r.get::<String, _>(0))
+                    .collect::<Vec<String>>(),
+            );
+        }
+        assert_eq!(masked[0], masked[1]);
+    }
+}
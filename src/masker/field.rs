@@ -1,13 +1,17 @@
-use std::{cmp::Ordering, fmt::Display, str::FromStr};
+use std::{cmp::Ordering, collections::HashSet, fmt::Display, str::FromStr, sync::Mutex};
 
-use crate::masker::generator::{new_from_yaml, Generator, Options};
+use rand::{rngs::StdRng, Rng};
+use regex::Regex;
+
+use crate::masker::generator::{new_from_yaml, Generator, Locale, Options, ORIGINAL_VALUE_PREFIX};
 
 use super::{
     error::{ConfigParseError, ConfigParseErrorKind},
-    generator::{GeneratedValue, GeneratorError},
+    generator::{GeneratedValue, GeneratorError, GeneratorErrorKind},
+    seed::SeedKey,
 };
 
-#[derive(PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum FieldKind {
     FirstName,
     LastName,
@@ -18,6 +22,9 @@ pub enum FieldKind {
     StateName,
     Template,
     Iban,
+    Email,
+    PhoneNumber,
+    Username,
     Unknown(String),
 }
 
@@ -35,15 +42,108 @@ impl FromStr for FieldKind {
             "StateName" => Ok(Self::StateName),
             "Template" => Ok(Self::Template),
             "Iban" => Ok(Self::Iban),
+            "Email" => Ok(Self::Email),
+            "PhoneNumber" => Ok(Self::PhoneNumber),
+            "Username" => Ok(Self::Username),
             _ => Ok(Self::Unknown(s.to_string())),
         }
     }
 }
 
+// What happens to the generated value that doesn't fit the column it's written to, either because
+// it's too long or because it has characters the column charset can't hold.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OverflowPolicy {
+    // Value is cut to the column length and unsupported characters are replaced with "?"
+    #[default]
+    Truncate,
+    // Masking fails, so that the value never gets changed behind the user's back
+    Reject,
+}
+
+// Column the values of the field are written to. Unique fields check their values the way the
+// column stores and compares them, since values that differ only until they're fitted (or only in
+// case, under a case insensitive collation) still collide in the column.
+pub trait FieldColumn {
+    // Makes the value fit the column, or tells why it can't be written to it
+    fn fit(&self, val: GeneratedValue, policy: OverflowPolicy) -> Result<GeneratedValue, String>;
+    // Values the column considers equal share the same key
+    fn unique_key(&self, val: String) -> String;
+}
+
+// Original value of the field is kept when the value of the column matches the pattern. Rule
+// checks either the field itself, or another column of the row, which is either a key column or
+// a masked one (its original value then).
+struct KeepRule {
+    column: Option<String>,
+    pattern: Regex,
+}
+
+impl KeepRule {
+    fn new_from_yaml(
+        column: Option<String>,
+        yaml: &serde_yaml::Value,
+    ) -> Result<Self, ConfigParseError> {
+        let field = String::from("keep_if");
+        let pattern = match yaml.as_str() {
+            Some(s) => Regex::new(s).map_err(|_| ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from(s)),
+                field: field.clone(),
+            })?,
+            None => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                    field,
+                })
+            }
+        };
+        Ok(Self { column, pattern })
+    }
+
+    fn matches(&self, opts: &Options, original: Option<&str>) -> Result<bool, GeneratorError> {
+        let column = match &self.column {
+            Some(c) => c,
+            None => return Ok(original.is_some_and(|v| self.pattern.is_match(v))),
+        };
+        let val = opts
+            .get(&format!("{ORIGINAL_VALUE_PREFIX}{column}"))
+            .or_else(|| opts.get(column))
+            .ok_or_else(|| GeneratorError {
+                generator_name: String::from("keep_if"),
+                kind: GeneratorErrorKind::UnknownColumn(column.clone()),
+            })?;
+        Ok(match val {
+            GeneratedValue::String(s) | GeneratedValue::Number(s) => self.pattern.is_match(s),
+            _ => false,
+        })
+    }
+}
+
+fn unique_key(column: Option<&dyn FieldColumn>, val: String) -> String {
+    match column {
+        Some(c) => c.unique_key(val),
+        None => val,
+    }
+}
+
+// Number of times the generator of a unique field is run for a single row before giving up
+const UNIQUE_ATTEMPTS: usize = 100;
+
 pub struct Field {
     field_name: String,
     pub kind: FieldKind,
     generator: Box<dyn Generator>,
+    on_overflow: OverflowPolicy,
+    deterministic: bool,
+    seed_key: Option<SeedKey>,
+    preserve_null: bool,
+    null_probability: f64,
+    keep_if: Vec<KeepRule>,
+    // Every value a unique field can't issue anymore: the ones already in the column and the ones
+    // issued during the run
+    issued: Option<Mutex<HashSet<String>>>,
+    // Config the field has been created from, along with the locale it has inherited
+    config: String,
 }
 
 impl Field {
@@ -52,14 +152,118 @@ impl Field {
             field_name,
             kind,
             generator,
+            on_overflow: OverflowPolicy::default(),
+            deterministic: false,
+            seed_key: None,
+            preserve_null: false,
+            null_probability: 0.0,
+            keep_if: vec![],
+            issued: None,
+            config: String::new(),
+        }
+    }
+
+    pub fn with_overflow_policy(mut self, on_overflow: OverflowPolicy) -> Self {
+        self.on_overflow = on_overflow;
+        self
+    }
+
+    // Deterministic field derives its values from the original value of the column rather than
+    // from the given RNG. Key is set separately, since the secret is shared by all the fields.
+    pub fn set_seed_key(&mut self, seed_key: SeedKey) {
+        self.deterministic = true;
+        self.seed_key = Some(seed_key);
+    }
+
+    // Deterministic fields are seeded from the original value, so that it's always masked the
+    // same way. NULL has nothing to be seeded from, so it gets a random value. NULL and empty
+    // values are kept as they are when the field preserves them, and the rest of the values are
+    // replaced with NULL with the field's null probability. Values allowed by any of the keep_if
+    // rules are checked separately (see kept_value), since they are never changed. Generated
+    // values are fitted to the column, if any. Unique fields retry the generator until it gives a
+    // value that hasn't been issued yet once fitted, NULL aside, since it never violates UNIQUE
+    // constraints.
+    pub fn generate(
+        &self,
+        opts: &Options,
+        original: Option<&str>,
+        rng: &mut StdRng,
+        column: Option<&dyn FieldColumn>,
+    ) -> Result<GeneratedValue, GeneratorError> {
+        if self.preserve_null {
+            match original {
+                None => return Ok(GeneratedValue::Null),
+                Some("") => return Ok(GeneratedValue::String(String::new())),
+                Some(_) => (),
+            }
         }
+        let mut seeded;
+        let rng = match (&self.seed_key, original) {
+            (Some(key), Some(val)) => {
+                seeded = key.rng_for(val);
+                &mut seeded
+            }
+            _ => rng,
+        };
+        if self.null_probability > 0.0 && rng.gen_bool(self.null_probability) {
+            return Ok(GeneratedValue::Null);
+        }
+        let fit = |val| match column {
+            Some(c) => c
+                .fit(val, self.get_overflow_policy())
+                .map_err(|reason| GeneratorError {
+                    generator_name: self.field_name.clone(),
+                    kind: GeneratorErrorKind::ValueDoesntFitColumn(reason),
+                }),
+            None => Ok(val),
+        };
+        let issued = match &self.issued {
+            Some(issued) => issued,
+            None => return fit(self.generator.generate(opts, rng)?),
+        };
+        for _ in 0..UNIQUE_ATTEMPTS {
+            let val = fit(self.generator.generate(opts, rng)?)?;
+            let text = match &val {
+                GeneratedValue::String(s) | GeneratedValue::Number(s) => s.clone(),
+                other => other.to_string(),
+            };
+            if matches!(val, GeneratedValue::Null)
+                || issued.lock().unwrap().insert(unique_key(column, text))
+            {
+                return Ok(val);
+            }
+        }
+        Err(GeneratorError {
+            generator_name: self.field_name.clone(),
+            kind: GeneratorErrorKind::UniqueValuesExhausted(UNIQUE_ATTEMPTS),
+        })
     }
 
-    pub fn generate(&self, opts: &Options) -> Result<GeneratedValue, GeneratorError> {
-        self.generator.generate(opts)
+    pub fn has_keep_rules(&self) -> bool {
+        !self.keep_if.is_empty()
     }
 
-    pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
+    // Original value if it's allowed by any of the keep_if rules, none if the value has to be
+    // masked. Kept value is already in the column, so unique fields have it reserved.
+    pub fn kept_value(
+        &self,
+        opts: &Options,
+        original: Option<&str>,
+    ) -> Result<Option<GeneratedValue>, GeneratorError> {
+        for rule in &self.keep_if {
+            if rule.matches(opts, original)? {
+                return Ok(Some(original.map_or(GeneratedValue::Null, |v| {
+                    GeneratedValue::String(String::from(v))
+                })));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn new_from_yaml(
+        yaml: &serde_yaml::Value,
+        locale: Locale,
+    ) -> Result<Self, ConfigParseError> {
         let field_name = String::from("name");
         let name = match yaml[field_name.as_str()].as_str() {
             Some(s) => String::from(s),
@@ -70,18 +274,157 @@ impl Field {
                 })
             }
         };
-        let (kind, generator) = new_from_yaml(yaml)?;
-        Ok(Self::new(name, kind, generator))
+        let field = String::from("on_overflow");
+        let on_overflow = match &yaml[field.as_str()] {
+            serde_yaml::Value::Null => OverflowPolicy::default(),
+            serde_yaml::Value::String(s) => match s.as_str() {
+                "truncate" => OverflowPolicy::Truncate,
+                "reject" => OverflowPolicy::Reject,
+                other => {
+                    return Err(ConfigParseError {
+                        kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from(other)),
+                        field,
+                    })
+                }
+            },
+            _ => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                    field,
+                })
+            }
+        };
+        let field = "deterministic";
+        let deterministic = match &yaml[field] {
+            serde_yaml::Value::Null => false,
+            serde_yaml::Value::Bool(b) => *b,
+            _ => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                    field: String::from(field),
+                })
+            }
+        };
+        let field = "preserve_null";
+        let preserve_null = match &yaml[field] {
+            serde_yaml::Value::Null => false,
+            serde_yaml::Value::Bool(b) => *b,
+            _ => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                    field: String::from(field),
+                })
+            }
+        };
+        let field = "null_probability";
+        let null_probability = match &yaml[field] {
+            serde_yaml::Value::Null => 0.0,
+            serde_yaml::Value::Number(n) => match n.as_f64() {
+                Some(p) if (0.0..=1.0).contains(&p) => p,
+                _ => {
+                    return Err(ConfigParseError {
+                        kind: ConfigParseErrorKind::UnexpectedFieldValue(n.to_string()),
+                        field: String::from(field),
+                    })
+                }
+            },
+            _ => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                    field: String::from(field),
+                })
+            }
+        };
+        // keep_if is either a pattern for the value of the field itself, or a mapping of columns of
+        // the row to patterns for their values
+        let field = "keep_if";
+        let keep_if = match &yaml[field] {
+            serde_yaml::Value::Null => vec![],
+            serde_yaml::Value::Mapping(m) => m
+                .iter()
+                .map(|(column, pattern)| match column.as_str() {
+                    Some(c) => KeepRule::new_from_yaml(Some(String::from(c)), pattern),
+                    None => Err(ConfigParseError {
+                        kind: ConfigParseErrorKind::UnexpectedFieldType,
+                        field: String::from(field),
+                    }),
+                })
+                .collect::<Result<Vec<KeepRule>, ConfigParseError>>()?,
+            pattern => vec![KeepRule::new_from_yaml(None, pattern)?],
+        };
+        // Deterministic field gives the same original value the same masked one in every table,
+        // which a unique field can't promise once it has to skip a value issued before
+        let field = "unique";
+        let unique = match &yaml[field] {
+            serde_yaml::Value::Null => false,
+            serde_yaml::Value::Bool(true) if deterministic => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from(
+                        "true, since the field is deterministic",
+                    )),
+                    field: String::from(field),
+                })
+            }
+            serde_yaml::Value::Bool(b) => *b,
+            _ => {
+                return Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                    field: String::from(field),
+                })
+            }
+        };
+        let (kind, generator) = new_from_yaml(yaml, locale)?;
+        let mut field = Self::new(name, kind, generator).with_overflow_policy(on_overflow);
+        field.deterministic = deterministic;
+        field.preserve_null = preserve_null;
+        field.null_probability = null_probability;
+        field.keep_if = keep_if;
+        field.issued = unique.then(|| Mutex::new(HashSet::new()));
+        field.config = format!(
+            "{locale:?}\0{}",
+            serde_yaml::to_string(yaml).expect("config is always serializable")
+        );
+        Ok(field)
     }
 
     pub fn get_column_name(&self) -> &String {
         &self.field_name
     }
 
+    pub fn get_config(&self) -> &str {
+        &self.config
+    }
+
+    pub fn get_overflow_policy(&self) -> OverflowPolicy {
+        self.on_overflow
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.issued.is_some()
+    }
+
+    // Keeps a unique field from issuing the given values, so that it never repeats the ones that
+    // are already in the column
+    pub fn reserve_values(&self, values: Vec<String>, column: Option<&dyn FieldColumn>) {
+        if let Some(issued) = &self.issued {
+            issued
+                .lock()
+                .unwrap()
+                .extend(values.into_iter().map(|v| unique_key(column, v)));
+        }
+    }
+
     // This function is needed for comparing the fields in terms of simple and composed
     // generators.
     fn has_composed_generator_kind(&self) -> bool {
-        matches!(self.kind, FieldKind::Template)
+        matches!(
+            self.kind,
+            FieldKind::Template | FieldKind::Email | FieldKind::Username
+        )
     }
 }
 
@@ -120,3 +463,245 @@ impl Display for Field {
         write!(f, "field_name: {}", self.field_name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fake::{faker::name::raw::*, locales::EN, Fake};
+    use sqlx::Row;
+
+    use super::*;
+    use crate::database::shared;
+    use crate::database::test_db::{get_adapter, get_test_conn};
+    use crate::database::{Checkpoint, Output};
+    use crate::masker::generator::SimpleGenerator;
+    use crate::masker::{Entity, PkColumn, PkType};
+
+    #[tokio::test]
+    async fn deterministic_fields_mask_same_values_the_same_way() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let seed_key = SeedKey::new("secret");
+        let mut field = Field::new(
+            "comment".to_string(),
+            FieldKind::FirstName,
+            Box::new(SimpleGenerator::new(|_: &Options, rng: &mut StdRng| {
+                Ok(GeneratedValue::String(FirstName(EN).fake_with_rng(rng)))
+            })),
+        );
+        field.set_seed_key(seed_key.clone());
+        let entity = Entity::new(
+            "payments".to_string(),
+            vec![
+                PkColumn::new("customerNumber".to_string(), PkType::Int),
+                PkColumn::new("checkNumber".to_string(), PkType::String),
+            ],
+            vec![field],
+        );
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let comments = sqlx::query("SELECT DISTINCT comment FROM payments")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect::<Vec<String>>();
+        let expected: String = FirstName(EN).fake_with_rng(&mut seed_key.rng_for("original"));
+        assert_eq!(comments, vec![expected]);
+    }
+
+    #[tokio::test]
+    async fn fields_get_original_values_of_the_row() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let yaml = serde_yaml::from_str(
+            "name: contactFirstName
+kind: Template
+template: \"%(orig.contactFirstName) (%(customerNumber))\"",
+        )
+        .unwrap();
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new_from_yaml(&yaml, Locale::default()).unwrap()],
+        );
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let masked = sqlx::query("SELECT contactFirstName FROM customers ORDER BY customerNumber")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect::<Vec<String>>();
+        assert_eq!(
+            masked,
+            vec!["Carine (103)", "Jean (112)", "Peter (114)", "Janine (119)"]
+        );
+    }
+
+    #[tokio::test]
+    async fn fields_preserving_nulls_keep_nulls_and_empty_values() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        sqlx::query("CREATE TABLE contacts (id INTEGER PRIMARY KEY, phone TEXT);")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO contacts VALUES (1, '555-0100'), (2, NULL), (3, '');")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let yaml = serde_yaml::from_str(
+            "name: phone
+kind: Template
+template: \"%{dddd}\"
+preserve_null: true",
+        )
+        .unwrap();
+        let entity = Entity::new(
+            "contacts".to_string(),
+            vec![PkColumn::new("id".to_string(), PkType::Int)],
+            vec![Field::new_from_yaml(&yaml, Locale::default()).unwrap()],
+        );
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let masked = sqlx::query("SELECT phone FROM contacts ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<Option<String>, _>(0))
+            .collect::<Vec<Option<String>>>();
+        assert_eq!(masked[0].as_ref().map(|p| p.len()), Some(4));
+        assert_eq!(masked[1], None);
+        assert_eq!(masked[2], Some(String::new()));
+    }
+
+    #[tokio::test]
+    async fn fields_keep_values_of_rows_allowed_by_them() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        let yaml = serde_yaml::from_str(
+            "name: contactFirstName
+kind: Template
+template: masked
+keep_if:
+  customerNumber: ^(103|119)$",
+        )
+        .unwrap();
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new_from_yaml(&yaml, Locale::default()).unwrap()],
+        );
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let masked = sqlx::query("SELECT contactFirstName FROM customers ORDER BY customerNumber")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect::<Vec<String>>();
+        assert_eq!(masked, vec!["Carine", "masked", "masked", "Janine"]);
+    }
+
+    #[test]
+    fn unique_fields_cant_be_deterministic() {
+        let yaml = serde_yaml::from_str(
+            "name: contactFirstName
+kind: FirstName
+deterministic: true
+unique: true",
+        )
+        .unwrap();
+        let err = Field::new_from_yaml(&yaml, Locale::default())
+            .err()
+            .unwrap();
+        assert_eq!(err.field, "unique");
+    }
+
+    #[tokio::test]
+    async fn unique_fields_never_reuse_values_left_in_column() {
+        let adapter = get_adapter();
+        let pool = get_test_conn().await;
+        for query_str in [
+            "UPDATE customers SET contactFirstName = CAST(customerNumber % 10 AS TEXT)",
+            "CREATE UNIQUE INDEX customers_name ON customers (contactFirstName)",
+        ] {
+            sqlx::query(query_str).execute(&pool).await.unwrap();
+        }
+        let yaml = serde_yaml::from_str(
+            "name: contactFirstName
+kind: Template
+template: \"%{d}\"
+unique: true
+keep_if:
+  customerNumber: ^103$",
+        )
+        .unwrap();
+        // 103 keeps its value, 119 is filtered out, and 112 and 114 hold theirs until they're
+        // masked, so none of the values could be generated again
+        let entity = Entity::new(
+            "customers".to_string(),
+            vec![PkColumn::new("customerNumber".to_string(), PkType::Int)],
+            vec![Field::new_from_yaml(&yaml, Locale::default()).unwrap()],
+        )
+        .with_filter("customerNumber <> 119".to_string());
+
+        shared::mask_table(
+            &adapter,
+            &entity,
+            &pool,
+            &Checkpoint::new(None),
+            Output::Database,
+        )
+        .await
+        .unwrap();
+        let values = sqlx::query("SELECT contactFirstName FROM customers ORDER BY customerNumber")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect::<Vec<String>>();
+        assert_eq!((values[0].as_str(), values[3].as_str()), ("3", "9"));
+        for masked in &values[1..3] {
+            assert!(!["2", "3", "4", "9"].contains(&masked.as_str()));
+        }
+        assert_ne!(values[1], values[2]);
+    }
+}
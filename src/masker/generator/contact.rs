@@ -0,0 +1,374 @@
+use deunicode::deunicode;
+use rand::{rngs::StdRng, Rng};
+
+use crate::masker::{
+    error::{ConfigParseError, ConfigParseErrorKind},
+    FieldKind,
+};
+
+use super::{
+    error::GeneratorErrorKind, GeneratedValue, Generator, GeneratorError, Locale, Options,
+};
+
+// Mobile number format of every supported country in the international notation, # stands for
+// a random digit.
+const PHONE_FORMATS: &[(&str, &str)] = &[
+    ("AT", "+43 66# #######"),
+    ("AU", "+61 4## ### ###"),
+    ("BE", "+32 47# ## ## ##"),
+    ("BR", "+55 11 9####-####"),
+    ("CA", "+1 2##-###-####"),
+    ("CH", "+41 7# ### ## ##"),
+    ("CN", "+86 13# #### ####"),
+    ("DE", "+49 15# ########"),
+    ("ES", "+34 6## ### ###"),
+    ("FR", "+33 6 ## ## ## ##"),
+    ("GB", "+44 7### ######"),
+    ("IN", "+91 9#### #####"),
+    ("IT", "+39 3## ### ####"),
+    ("JP", "+81 90-####-####"),
+    ("NL", "+31 6 ########"),
+    ("PL", "+48 5## ### ###"),
+    ("SE", "+46 7# ### ## ##"),
+    ("US", "+1 2##-###-####"),
+];
+
+// Country of the phone number by its dialing code, if it's one of the supported ones. +1 is
+// shared by Canada and the US, and is taken for the latter.
+pub fn phone_country(number: &str) -> Option<&'static str> {
+    let digits = |s: &str| s.chars().filter(char::is_ascii_digit).collect::<String>();
+    let number = digits(number);
+    PHONE_FORMATS
+        .iter()
+        .rev()
+        .find(|(_, format)| number.starts_with(&digits(format.split(' ').next().unwrap())))
+        .map(|(country, _)| *country)
+}
+
+// Turns the name into a part of an email address or a username: it's transliterated to ASCII and
+// lower cased, with everything but letters and digits left out (Anne-Marie O'Neil becomes
+// annemarieoneil).
+fn name_part(name: &str) -> String {
+    deunicode(name)
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+// Names that email addresses and usernames are built from: either the values of the given
+// columns of the row (the masked ones, if the columns are masked as well) or new ones in the
+// locale of the field.
+struct NameSource {
+    first_name: Option<String>,
+    last_name: Option<String>,
+    locale: Locale,
+}
+
+impl NameSource {
+    fn new_from_yaml(yaml: &serde_yaml::Value, locale: Locale) -> Result<Self, ConfigParseError> {
+        let column = |field: &str| match &yaml[field] {
+            serde_yaml::Value::Null => Ok(None),
+            serde_yaml::Value::String(s) => Ok(Some(s.clone())),
+            _ => Err(ConfigParseError {
+                field: field.to_string(),
+                kind: ConfigParseErrorKind::UnexpectedFieldType,
+            }),
+        };
+        Ok(Self {
+            first_name: column("first_name")?,
+            last_name: column("last_name")?,
+            locale,
+        })
+    }
+
+    fn name<T: Generator>(
+        &self,
+        column: &Option<String>,
+        kind: FieldKind,
+        opts: &Options,
+        rng: &mut StdRng,
+    ) -> Result<String, GeneratorError> {
+        let name = match column {
+            Some(c) => match opts.get(c) {
+                Some(GeneratedValue::String(s)) | Some(GeneratedValue::Number(s)) => name_part(s),
+                Some(_) => String::new(),
+                None => {
+                    return Err(GeneratorError::new::<T>(GeneratorErrorKind::UnknownColumn(
+                        c.clone(),
+                    )))
+                }
+            },
+            None => name_part(&self.locale.fake(&kind, rng).unwrap_or_default()),
+        };
+        // NULL and names that have nothing left after the transliteration are replaced
+        if name.is_empty() {
+            return Ok(name_part(&Locale::En.fake(&kind, rng).unwrap_or_default()));
+        }
+        Ok(name)
+    }
+
+    fn names<T: Generator>(
+        &self,
+        opts: &Options,
+        rng: &mut StdRng,
+    ) -> Result<(String, String), GeneratorError> {
+        Ok((
+            self.name::<T>(&self.first_name, FieldKind::FirstName, opts, rng)?,
+            self.name::<T>(&self.last_name, FieldKind::LastName, opts, rng)?,
+        ))
+    }
+}
+
+// Number of random digits appended to the name, so that people of the same name get different
+// values
+fn read_digits(yaml: &serde_yaml::Value) -> Result<usize, ConfigParseError> {
+    let field = "digits";
+    match &yaml[field] {
+        serde_yaml::Value::Null => Ok(0),
+        v => match v.as_u64() {
+            Some(n) => Ok(n as usize),
+            None => Err(ConfigParseError {
+                field: field.to_string(),
+                kind: ConfigParseErrorKind::UnexpectedFieldType,
+            }),
+        },
+    }
+}
+
+fn random_digits(n: usize, rng: &mut StdRng) -> String {
+    (0..n)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect()
+}
+
+// Email address of the form first.last@domain. Domain is example.com unless configured, so that
+// masked addresses never reach a real mailbox.
+pub struct EmailGenerator {
+    names: NameSource,
+    domain: String,
+    digits: usize,
+}
+
+impl EmailGenerator {
+    pub fn new_from_yaml(
+        yaml: &serde_yaml::Value,
+        locale: Locale,
+    ) -> Result<Self, ConfigParseError> {
+        let field = "domain";
+        let domain = match &yaml[field] {
+            serde_yaml::Value::Null => String::from("example.com"),
+            serde_yaml::Value::String(s) if s.is_empty() || s.contains(['@', ' ']) => {
+                return Err(ConfigParseError {
+                    field: field.to_string(),
+                    kind: ConfigParseErrorKind::UnexpectedFieldValue(s.clone()),
+                })
+            }
+            serde_yaml::Value::String(s) => s.clone(),
+            _ => {
+                return Err(ConfigParseError {
+                    field: field.to_string(),
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                })
+            }
+        };
+        Ok(Self {
+            names: NameSource::new_from_yaml(yaml, locale)?,
+            domain,
+            digits: read_digits(yaml)?,
+        })
+    }
+}
+
+impl Generator for EmailGenerator {
+    fn generate(&self, opts: &Options, rng: &mut StdRng) -> Result<GeneratedValue, GeneratorError> {
+        let (first, last) = self.names.names::<Self>(opts, rng)?;
+        Ok(GeneratedValue::String(format!(
+            "{first}.{last}{}@{}",
+            random_digits(self.digits, rng),
+            self.domain
+        )))
+    }
+}
+
+// Username of the form of the first letter of the first name followed by the last name (jsmith)
+pub struct UsernameGenerator {
+    names: NameSource,
+    digits: usize,
+}
+
+impl UsernameGenerator {
+    pub fn new_from_yaml(
+        yaml: &serde_yaml::Value,
+        locale: Locale,
+    ) -> Result<Self, ConfigParseError> {
+        Ok(Self {
+            names: NameSource::new_from_yaml(yaml, locale)?,
+            digits: read_digits(yaml)?,
+        })
+    }
+}
+
+impl Generator for UsernameGenerator {
+    fn generate(&self, opts: &Options, rng: &mut StdRng) -> Result<GeneratedValue, GeneratorError> {
+        let (first, last) = self.names.names::<Self>(opts, rng)?;
+        Ok(GeneratedValue::String(format!(
+            "{}{last}{}",
+            first.get(..1).unwrap_or_default(),
+            random_digits(self.digits, rng)
+        )))
+    }
+}
+
+// Mobile phone number in the format of the configured country, US by default
+pub struct PhoneNumberGenerator {
+    format: &'static str,
+}
+
+impl PhoneNumberGenerator {
+    pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
+        let field = "country";
+        let country = match &yaml[field] {
+            serde_yaml::Value::Null => String::from("US"),
+            serde_yaml::Value::String(s) => s.to_uppercase(),
+            _ => {
+                return Err(ConfigParseError {
+                    field: field.to_string(),
+                    kind: ConfigParseErrorKind::UnexpectedFieldType,
+                })
+            }
+        };
+        match PHONE_FORMATS.iter().find(|(c, _)| *c == country) {
+            Some((_, format)) => Ok(Self { format }),
+            None => Err(ConfigParseError {
+                field: field.to_string(),
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(country),
+            }),
+        }
+    }
+}
+
+impl Generator for PhoneNumberGenerator {
+    fn generate(&self, _: &Options, rng: &mut StdRng) -> Result<GeneratedValue, GeneratorError> {
+        Ok(GeneratedValue::String(
+            self.format
+                .chars()
+                .map(|c| match c {
+                    '#' => char::from(b'0' + rng.gen_range(0..10)),
+                    c => c,
+                })
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{phone_country, EmailGenerator, PhoneNumberGenerator, UsernameGenerator};
+    use crate::masker::{
+        error::{ConfigParseError, ConfigParseErrorKind},
+        generator::{GeneratedValue, Generator, GeneratorErrorKind, Locale, Options},
+    };
+
+    #[test]
+    fn email_is_built_from_masked_names() {
+        let yaml = serde_yaml::from_str(
+            "domain: example.test
+first_name: contactFirstName
+last_name: contactLastName",
+        )
+        .unwrap();
+        let generator = EmailGenerator::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let (first, last) = (
+            String::from("contactFirstName"),
+            String::from("contactLastName"),
+        );
+        let options: Options = HashMap::from([
+            (&first, GeneratedValue::String("Anne-Marie".to_string())),
+            (&last, GeneratedValue::String("O'Neil Müller".to_string())),
+        ]);
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(
+            generator.generate(&options, &mut rng).unwrap(),
+            GeneratedValue::String("annemarie.oneilmuller@example.test".to_string())
+        );
+    }
+
+    #[test]
+    fn username_is_first_letter_followed_by_last_name() {
+        let yaml = serde_yaml::from_str(
+            "digits: 2
+first_name: contactFirstName
+last_name: contactLastName",
+        )
+        .unwrap();
+        let generator = UsernameGenerator::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let (first, last) = (
+            String::from("contactFirstName"),
+            String::from("contactLastName"),
+        );
+        let options: Options = HashMap::from([
+            (&first, GeneratedValue::String("John".to_string())),
+            (&last, GeneratedValue::String("Smith".to_string())),
+        ]);
+        let username = generator
+            .generate(&options, &mut StdRng::seed_from_u64(1))
+            .unwrap()
+            .to_string();
+        assert!(regex::Regex::new(r#"^"jsmith[0-9]{2}"$"#)
+            .unwrap()
+            .is_match(&username));
+
+        // Names without configured columns are made up in the locale of the field
+        let generator =
+            UsernameGenerator::new_from_yaml(&serde_yaml::Value::Null, Locale::JaJp).unwrap();
+        let username = generator
+            .generate(&HashMap::new(), &mut StdRng::seed_from_u64(1))
+            .unwrap()
+            .to_string();
+        assert!(regex::Regex::new(r#"^"[a-z0-9]+"$"#)
+            .unwrap()
+            .is_match(&username));
+    }
+
+    #[test]
+    fn name_column_must_be_in_the_row() {
+        let yaml = serde_yaml::from_str("first_name: contactFirstName").unwrap();
+        let generator = EmailGenerator::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let err = generator
+            .generate(&HashMap::new(), &mut StdRng::seed_from_u64(1))
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            GeneratorErrorKind::UnknownColumn(String::from("contactFirstName"))
+        );
+    }
+
+    #[test]
+    fn phone_number_follows_the_country_format() {
+        let yaml = serde_yaml::from_str("country: de").unwrap();
+        let generator = PhoneNumberGenerator::new_from_yaml(&yaml).unwrap();
+        let number = generator
+            .generate(&HashMap::new(), &mut StdRng::seed_from_u64(1))
+            .unwrap()
+            .to_string();
+        assert!(regex::Regex::new(r#"^"\+49 15\d \d{8}"$"#)
+            .unwrap()
+            .is_match(&number));
+        assert_eq!(phone_country("+49 151 23456789"), Some("DE"));
+        assert_eq!(phone_country("+1 (212) 555-0100"), Some("US"));
+
+        let yaml = serde_yaml::from_str("country: XX").unwrap();
+        assert_eq!(
+            PhoneNumberGenerator::new_from_yaml(&yaml).err().unwrap(),
+            ConfigParseError {
+                field: String::from("country"),
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from("XX")),
+            }
+        );
+    }
+}
@@ -28,6 +28,27 @@ impl Display for GeneratorError {
             GeneratorErrorKind::UnexpectedCountryCodeForIban(code) => {
                 write!(f, "unexpected country code for iban - {}", code)
             }
+            GeneratorErrorKind::UniqueValuesExhausted(attempts) => {
+                write!(
+                    f,
+                    "couldn't generate a unique value for {} in {} attempts, its values have run out",
+                    self.generator_name, attempts
+                )
+            }
+            GeneratorErrorKind::ValueDoesntFitColumn(reason) => {
+                write!(
+                    f,
+                    "value generated for {} doesn't fit the column: {}",
+                    self.generator_name, reason
+                )
+            }
+            GeneratorErrorKind::UnknownColumn(column) => {
+                write!(
+                    f,
+                    "column {} referred to by {} is neither a key nor a masked one",
+                    column, self.generator_name
+                )
+            }
         }
     }
 }
@@ -57,4 +78,7 @@ pub enum GeneratorErrorKind {
     GenerateIban,
     GenerateIbanForCountryCode(String),
     UnexpectedCountryCodeForIban(String),
+    UnknownColumn(String),
+    UniqueValuesExhausted(usize),
+    ValueDoesntFitColumn(String),
 }
@@ -1,7 +1,6 @@
 use std::str::FromStr;
 
-use fake::{locales::EN, Fake};
-use rand::{distributions::Uniform, thread_rng, Rng};
+use rand::{distributions::Uniform, rngs::StdRng, Rng};
 
 use super::{
     token::{Token, TokenKind},
@@ -9,7 +8,10 @@ use super::{
 };
 use crate::masker::{
     error::{ConfigParseError, ConfigParseErrorKind},
-    generator::{error::GeneratorErrorKind, GeneratedValue, Generator, GeneratorError, Options},
+    generator::{
+        encode_hex, error::GeneratorErrorKind, GeneratedValue, Generator, GeneratorError, Locale,
+        Options,
+    },
     FieldKind,
 };
 
@@ -19,10 +21,14 @@ pub struct TemplatedGenerator {
     digits_set: Uniform<char>,
     template: String,
     tokens: Vec<Token>,
+    locale: Locale,
 }
 
 impl TemplatedGenerator {
-    pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
+    pub fn new_from_yaml(
+        yaml: &serde_yaml::Value,
+        locale: Locale,
+    ) -> Result<Self, ConfigParseError> {
         let field = "template";
         match yaml[field].as_str() {
             Some(t) => {
@@ -42,6 +48,7 @@ impl TemplatedGenerator {
                     digits_set: Uniform::new(char::from(0x30), char::from(0x39)),
                     template: t.to_string(),
                     tokens,
+                    locale,
                 })
             }
             None => Err(ConfigParseError {
@@ -53,10 +60,8 @@ impl TemplatedGenerator {
 }
 
 impl Generator for TemplatedGenerator {
-    fn generate(&self, opts: &Options) -> Result<GeneratedValue, GeneratorError> {
+    fn generate(&self, opts: &Options, rng: &mut StdRng) -> Result<GeneratedValue, GeneratorError> {
         let mut res = String::new();
-        use fake::faker::address::raw::*;
-        use fake::faker::name::raw::*;
         self.tokens.iter().try_for_each(|token| match &token.0 {
             // Simply add plain text to the result generated value
             TokenKind::Plain(s) => {
@@ -66,22 +71,19 @@ impl Generator for TemplatedGenerator {
             // Try replacing variable from options map
             TokenKind::Variable(v) => match opts.get(v) {
                 Some(val) => {
-                    res.push_str(match val {
-                        GeneratedValue::String(s) => s,
-                        GeneratedValue::Number(n) => n,
-                    });
+                    match val {
+                        GeneratedValue::String(s) => res.push_str(s),
+                        GeneratedValue::Number(n) => res.push_str(n),
+                        GeneratedValue::Bytes(b) => res.push_str(encode_hex(b).as_str()),
+                        // NULL is rendered as nothing
+                        GeneratedValue::Null => (),
+                    };
                     Ok(())
                 }
                 None => {
-                    match FieldKind::from_str(v).unwrap() {
-                        FieldKind::CityName => res.push_str(CityName(EN).fake::<String>().as_str()),
-                        FieldKind::CountryCode => res.push_str(CountryCode(EN).fake::<String>().as_str()),
-                        FieldKind::CountryName => res.push_str(CountryName(EN).fake::<String>().as_str()),
-                        FieldKind::FirstName => res.push_str(FirstName(EN).fake()),
-                        FieldKind::LastName => res.push_str(FirstName(EN).fake()),
-                        FieldKind::PostCode => res.push_str(PostCode(EN).fake::<String>().as_str()),
-                        FieldKind::StateName => res.push_str(StateName(EN).fake::<String>().as_str()),
-                        FieldKind::Unknown(_) | FieldKind::Iban | FieldKind::Template => return Err(GeneratorError::new::<Self>(
+                    match self.locale.fake(&FieldKind::from_str(v).unwrap(), rng) {
+                        Some(val) => res.push_str(val.as_str()),
+                        None => return Err(GeneratorError::new::<Self>(
                             GeneratorErrorKind::ParseTemplatedGenerator(TemplatedParserError::new(
                                 super::error::TemplateParserErrorKind::FailedToResolveValueFromTemplate(
                                     self.template.clone(),
@@ -97,7 +99,7 @@ impl Generator for TemplatedGenerator {
             // the result val
             TokenKind::CapitalLetterSeq(seq) => {
                 res.push_str(
-                    thread_rng()
+                    (&mut *rng)
                         .sample_iter(&self.upper_case_letters_set)
                         .take(seq.chars().count())
                         .collect::<String>()
@@ -109,7 +111,7 @@ impl Generator for TemplatedGenerator {
             // the result val
             TokenKind::LowerCaseLetterSeq(seq) => {
                 res.push_str(
-                    thread_rng()
+                    (&mut *rng)
                         .sample_iter(&self.lower_case_letters_set)
                         .take(seq.chars().count())
                         .collect::<String>()
@@ -121,7 +123,7 @@ impl Generator for TemplatedGenerator {
             // the result val
             TokenKind::DigitSeq(seq) => {
                 res.push_str(
-                    thread_rng()
+                    (&mut *rng)
                         .sample_iter(&self.digits_set)
                         .take(seq.chars().count())
                         .collect::<String>()
@@ -134,3 +136,55 @@ impl Generator for TemplatedGenerator {
         Ok(GeneratedValue::String(res))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::TemplatedGenerator;
+    use crate::masker::generator::{GeneratedValue, Generator, Locale, Options};
+
+    fn generate(template: &str, seed: u64) -> GeneratedValue {
+        let yaml = serde_yaml::from_str(format!("template: \"{template}\"").as_str()).unwrap();
+        let generator = TemplatedGenerator::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let options: Options = HashMap::new();
+        generator
+            .generate(&options, &mut StdRng::seed_from_u64(seed))
+            .unwrap()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_value() {
+        let template = "%(FirstName) %{Lllll}-%{dddd}";
+        assert_eq!(generate(template, 7), generate(template, 7));
+        assert_ne!(generate(template, 7), generate(template, 8));
+    }
+
+    #[test]
+    fn original_values_are_taken_from_options() {
+        let yaml = serde_yaml::from_str("template: \"%(orig.email)|%(orig.phone)\"").unwrap();
+        let generator = TemplatedGenerator::new_from_yaml(&yaml, Locale::default()).unwrap();
+        let (email, phone) = (String::from("orig.email"), String::from("orig.phone"));
+        let options: Options = HashMap::from([
+            (&email, GeneratedValue::String("jd@example.com".to_string())),
+            (&phone, GeneratedValue::Null),
+        ]);
+        assert_eq!(
+            generator
+                .generate(&options, &mut StdRng::seed_from_u64(7))
+                .unwrap(),
+            GeneratedValue::String("jd@example.com|".to_string())
+        );
+    }
+
+    #[test]
+    fn random_sequences_follow_the_template() {
+        let GeneratedValue::String(val) = generate("id-%{LLdddd}-%{ll}", 7) else {
+            panic!("expected templated value as string");
+        };
+        let r = regex::Regex::new(r"^id-[A-Z]{2}[0-9]{4}-[a-z]{2}$").unwrap();
+        assert!(r.is_match(&val), "{val}");
+    }
+}
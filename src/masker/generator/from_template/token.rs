@@ -18,7 +18,7 @@ enum VariableParserState {
     Plain,         // just string reading
     TokenEntry,    // '%' char detected
     VarBlockStart, // if '(' follows after '%'
-    VarTokenRead,  // whatever comes after #( and is alphanumerical or underscore
+    VarTokenRead,  // whatever comes after #( and is alphanumerical, underscore or dot
     SeqBlockStart, // if '{' follows after '%'
     SeqTokenRead,  // whatever comes after #( and is part of known rand sequence charset
 }
@@ -103,8 +103,9 @@ impl Token {
                         match tokens.pop() {
                             Some(mut token) => match token.0 {
                                 TokenKind::Variable(placeholder) => {
-                                    // Keep reading if letter, digit or _ has been met
-                                    if ch.is_ascii_alphanumeric() || ch == '_' {
+                                    // Keep reading if letter, digit, _ or . has been met, the
+                                    // latter separates prefix of original values (orig.email)
+                                    if ch.is_ascii_alphanumeric() || ch == '_' || ch == '.' {
                                         token.0 =
                                             TokenKind::Variable(format!("{}{}", placeholder, ch));
                                         tokens.push(token);
@@ -309,6 +310,17 @@ mod tests {
         assert_eq!(expected, res);
     }
 
+    #[test]
+    fn it_parses_variables_with_prefix() {
+        let template = String::from("%(orig.email)");
+        let res = Token::parse_tokens_from_template(&template).unwrap();
+
+        assert_eq!(
+            res,
+            vec![Token(TokenKind::Variable(String::from("orig.email")))]
+        );
+    }
+
     #[test]
     fn it_panics_on_unclosed_var_token() {
         let template = String::from("Company #%(id");
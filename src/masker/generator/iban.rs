@@ -1,5 +1,5 @@
 use iban::Iban;
-use rand::seq::SliceRandom;
+use rand::{rngs::StdRng, seq::SliceRandom};
 
 use crate::masker::error::{ConfigParseError, ConfigParseErrorKind};
 
@@ -55,11 +55,18 @@ impl IbanGenerator {
 }
 
 impl Generator for IbanGenerator {
-    fn generate(&self, _: &Options) -> Result<super::GeneratedValue, super::GeneratorError> {
-        let code = self.country_codes.choose(&mut rand::thread_rng()).ok_or(
-            GeneratorError::new::<Self>(GeneratorErrorKind::GenerateIban),
-        )?;
-        let iban = Iban::rand(code, &mut rand::thread_rng()).map_err(|_| {
+    fn generate(
+        &self,
+        _: &Options,
+        rng: &mut StdRng,
+    ) -> Result<super::GeneratedValue, super::GeneratorError> {
+        let code = self
+            .country_codes
+            .choose(rng)
+            .ok_or(GeneratorError::new::<Self>(
+                GeneratorErrorKind::GenerateIban,
+            ))?;
+        let iban = Iban::rand(code, rng).map_err(|_| {
             GeneratorError::new::<Self>(GeneratorErrorKind::GenerateIbanForCountryCode(
                 code.clone(),
             ))
@@ -100,13 +107,17 @@ fn fails_to_create_generator_when_unexpected_country_code_is_met() {
 
 #[test]
 fn generates_random_iban() {
+    use rand::SeedableRng;
     use std::collections::HashMap;
     let valid_yaml = "country_codes:
     - DE";
     let yaml: serde_yaml::Value = serde_yaml::from_str(valid_yaml).unwrap();
     let generator = IbanGenerator::new_from_yaml(&yaml).unwrap();
     let options: Options = HashMap::new();
-    if let GeneratedValue::String(iban) = generator.generate(&options).unwrap() {
+    if let GeneratedValue::String(iban) = generator
+        .generate(&options, &mut StdRng::from_entropy())
+        .unwrap()
+    {
         let r = regex::Regex::new(r"DE\d{20}").unwrap();
         assert!(r.is_match(iban.as_str()))
     } else {
@@ -116,6 +127,7 @@ fn generates_random_iban() {
 
 #[test]
 fn generates_random_iban_formatted() {
+    use rand::SeedableRng;
     use std::collections::HashMap;
     let valid_yaml = "country_codes:
     - DE
@@ -123,7 +135,10 @@ formatted: true";
     let yaml: serde_yaml::Value = serde_yaml::from_str(valid_yaml).unwrap();
     let generator = IbanGenerator::new_from_yaml(&yaml).unwrap();
     let options: Options = HashMap::new();
-    if let GeneratedValue::String(iban) = generator.generate(&options).unwrap() {
+    if let GeneratedValue::String(iban) = generator
+        .generate(&options, &mut StdRng::from_entropy())
+        .unwrap()
+    {
         let r = regex::Regex::new(r"DE\d{2} \d{4} \d{4} \d{4} \d{4} \d{2}").unwrap();
         assert!(r.is_match(iban.as_str()))
     } else {
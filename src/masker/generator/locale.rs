@@ -0,0 +1,129 @@
+use std::str::FromStr;
+
+use fake::{
+    locales::{Data, AR_SA, EN, FR_FR, JA_JP, PT_BR, ZH_CN, ZH_TW},
+    Fake,
+};
+use rand::rngs::StdRng;
+
+use crate::masker::{
+    error::{ConfigParseError, ConfigParseErrorKind},
+    FieldKind,
+};
+
+// Locales of the values generated by fake (names, cities, post codes...). These are all the
+// locales the fake crate provides, which notably doesn't have de_DE or any other European
+// locale but fr_FR.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Locale {
+    #[default]
+    En,
+    FrFr,
+    JaJp,
+    ZhCn,
+    ZhTw,
+    ArSa,
+    PtBr,
+}
+
+// Names of the locales as they're written in the config, listed in the error of unknown locale
+const SUPPORTED_LOCALES: &str = "en, fr_FR, ja_JP, zh_CN, zh_TW, ar_SA, pt_BR";
+
+impl FromStr for Locale {
+    type Err = ();
+
+    // Locale is accepted in any case and with either "_" or "-" as a separator (fr_FR, fr-fr)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "en" => Ok(Self::En),
+            "fr_fr" => Ok(Self::FrFr),
+            "ja_jp" => Ok(Self::JaJp),
+            "zh_cn" => Ok(Self::ZhCn),
+            "zh_tw" => Ok(Self::ZhTw),
+            "ar_sa" => Ok(Self::ArSa),
+            "pt_br" => Ok(Self::PtBr),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Locale {
+    // Reads the locale both from the top level of the config and from fields, which override it
+    pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Option<Self>, ConfigParseError> {
+        let field = "locale";
+        match &yaml[field] {
+            serde_yaml::Value::Null => Ok(None),
+            serde_yaml::Value::String(s) => match Self::from_str(s) {
+                Ok(locale) => Ok(Some(locale)),
+                Err(_) => Err(ConfigParseError {
+                    kind: ConfigParseErrorKind::UnexpectedFieldValue(format!(
+                        "{s} (supported locales are {SUPPORTED_LOCALES})"
+                    )),
+                    field: String::from(field),
+                }),
+            },
+            _ => Err(ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldType,
+                field: String::from(field),
+            }),
+        }
+    }
+
+    // Generates the value of the kind in the locale, if the kind is generated by fake at all
+    pub fn fake(self, kind: &FieldKind, rng: &mut StdRng) -> Option<String> {
+        match self {
+            Self::En => fake_in(EN, kind, rng),
+            Self::FrFr => fake_in(FR_FR, kind, rng),
+            Self::JaJp => fake_in(JA_JP, kind, rng),
+            Self::ZhCn => fake_in(ZH_CN, kind, rng),
+            Self::ZhTw => fake_in(ZH_TW, kind, rng),
+            Self::ArSa => fake_in(AR_SA, kind, rng),
+            Self::PtBr => fake_in(PT_BR, kind, rng),
+        }
+    }
+}
+
+fn fake_in<L: Data + Copy>(locale: L, kind: &FieldKind, rng: &mut StdRng) -> Option<String> {
+    use fake::faker::address::raw::*;
+    use fake::faker::name::raw::*;
+    match kind {
+        FieldKind::FirstName => Some(FirstName(locale).fake_with_rng(rng)),
+        FieldKind::LastName => Some(LastName(locale).fake_with_rng(rng)),
+        FieldKind::CityName => Some(CityName(locale).fake_with_rng(rng)),
+        FieldKind::CountryCode => Some(CountryCode(locale).fake_with_rng(rng)),
+        FieldKind::CountryName => Some(CountryName(locale).fake_with_rng(rng)),
+        FieldKind::PostCode => Some(PostCode(locale).fake_with_rng(rng)),
+        FieldKind::StateName => Some(StateName(locale).fake_with_rng(rng)),
+        FieldKind::Template
+        | FieldKind::Iban
+        | FieldKind::Email
+        | FieldKind::PhoneNumber
+        | FieldKind::Username
+        | FieldKind::Unknown(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::Locale;
+    use crate::masker::FieldKind;
+
+    #[test]
+    fn parses_locales_in_any_case() {
+        assert_eq!(Locale::from_str("fr_FR"), Ok(Locale::FrFr));
+        assert_eq!(Locale::from_str("ja-jp"), Ok(Locale::JaJp));
+        assert_eq!(Locale::from_str("de_DE"), Err(()));
+    }
+
+    #[test]
+    fn fakes_values_in_the_locale() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let name = Locale::JaJp.fake(&FieldKind::LastName, &mut rng).unwrap();
+        assert!(!name.is_ascii());
+        assert_eq!(Locale::JaJp.fake(&FieldKind::Template, &mut rng), None);
+    }
+}
@@ -1,24 +1,31 @@
+mod contact;
 mod error;
 mod from_template;
 mod iban;
+mod locale;
 mod simple_generator;
 
-pub use error::GeneratorError;
+pub use contact::{phone_country, EmailGenerator, PhoneNumberGenerator, UsernameGenerator};
+pub use error::{GeneratorError, GeneratorErrorKind};
 pub use from_template::TemplatedGenerator;
 pub use iban::IbanGenerator;
+pub use locale::Locale;
 pub use simple_generator::SimpleGenerator;
 
 use crate::masker::{
     error::{ConfigParseError, ConfigParseErrorKind},
     FieldKind,
 };
-use fake::{locales::EN, Fake};
+use rand::rngs::StdRng;
 use std::{collections::HashMap, fmt::Display, str::FromStr};
 
+#[derive(Debug, PartialEq, Clone)]
 #[non_exhaustive]
 pub enum GeneratedValue {
     String(String),
     Number(String),
+    Bytes(Vec<u8>),
+    Null,
 }
 
 impl Display for GeneratedValue {
@@ -26,73 +33,83 @@ impl Display for GeneratedValue {
         match &self {
             GeneratedValue::Number(n) => write!(f, "{n}"),
             GeneratedValue::String(s) => write!(f, "\"{s}\""),
+            GeneratedValue::Bytes(b) => write!(f, "X'{}'", encode_hex(b)),
+            GeneratedValue::Null => write!(f, "NULL"),
         }
     }
 }
 
+// Binary values (e.g. BINARY(16) UUID keys) are represented as upper case hex string wherever
+// they need to be printed.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+// Generators draw every random choice from the RNG they are given instead of their own, so that
+// the caller decides whether the value is random or derived from something (e.g. the original
+// value of deterministic fields).
 pub trait Generator: Sync + Send {
-    fn generate(&self, options: &Options) -> Result<GeneratedValue, GeneratorError>;
+    fn generate(
+        &self,
+        options: &Options,
+        rng: &mut StdRng,
+    ) -> Result<GeneratedValue, GeneratorError>;
 }
 
 pub type Options<'a> = HashMap<&'a String, GeneratedValue>;
 
+// Original values of the masked row are passed to the generators under the column name with this
+// prefix (e.g. orig.email), so that they never clash with the generated ones. NULLs are passed as
+// Null.
+pub const ORIGINAL_VALUE_PREFIX: &str = "orig.";
+
+// Generator of the field is created in the given locale, unless the field has its own
 pub fn new_from_yaml(
     yaml: &serde_yaml::Value,
+    locale: Locale,
 ) -> Result<(FieldKind, Box<dyn Generator>), ConfigParseError> {
-    use fake::faker::address::raw::*;
-    use fake::faker::name::raw::*;
+    let locale = Locale::new_from_yaml(yaml)?.unwrap_or(locale);
     match yaml["kind"].as_str() {
         Some(s) => match FieldKind::from_str(s).unwrap() {
-            FieldKind::FirstName => Ok((
-                FieldKind::FirstName,
-                Box::new(SimpleGenerator::new(|_: &Options| {
-                    Ok(GeneratedValue::String(FirstName(EN).fake()))
-                })),
-            )),
-            FieldKind::LastName => Ok((
-                FieldKind::LastName,
-                Box::new(SimpleGenerator::new(|_: &Options| {
-                    Ok(GeneratedValue::String(LastName(EN).fake()))
-                })),
-            )),
-            FieldKind::CityName => Ok((
-                FieldKind::CityName,
-                Box::new(SimpleGenerator::new(|_: &Options| {
-                    Ok(GeneratedValue::String(CityName(EN).fake()))
-                })),
-            )),
-            FieldKind::CountryCode => Ok((
-                FieldKind::CountryCode,
-                Box::new(SimpleGenerator::new(|_: &Options| {
-                    Ok(GeneratedValue::String(CountryCode(EN).fake()))
-                })),
-            )),
-            FieldKind::CountryName => Ok((
-                FieldKind::CountryName,
-                Box::new(SimpleGenerator::new(|_: &Options| {
-                    Ok(GeneratedValue::String(CountryName(EN).fake()))
-                })),
-            )),
-            FieldKind::PostCode => Ok((
-                FieldKind::PostCode,
-                Box::new(SimpleGenerator::new(|_: &Options| {
-                    Ok(GeneratedValue::String(PostCode(EN).fake()))
-                })),
-            )),
-            FieldKind::StateName => Ok((
-                FieldKind::StateName,
-                Box::new(SimpleGenerator::new(|_: &Options| {
-                    Ok(GeneratedValue::String(StateName(EN).fake()))
-                })),
-            )),
+            kind @ (FieldKind::FirstName
+            | FieldKind::LastName
+            | FieldKind::CityName
+            | FieldKind::CountryCode
+            | FieldKind::CountryName
+            | FieldKind::PostCode
+            | FieldKind::StateName) => {
+                let generator_kind = kind.clone();
+                Ok((
+                    kind,
+                    Box::new(SimpleGenerator::new(
+                        move |_: &Options, rng: &mut StdRng| {
+                            Ok(GeneratedValue::String(
+                                locale.fake(&generator_kind, rng).unwrap_or_default(),
+                            ))
+                        },
+                    )),
+                ))
+            }
             FieldKind::Template => Ok((
                 FieldKind::Template,
-                Box::new(TemplatedGenerator::new_from_yaml(yaml)?),
+                Box::new(TemplatedGenerator::new_from_yaml(yaml, locale)?),
             )),
             FieldKind::Iban => Ok((
                 FieldKind::Iban,
                 Box::new(IbanGenerator::new_from_yaml(yaml)?),
             )),
+            FieldKind::Email => Ok((
+                FieldKind::Email,
+                Box::new(EmailGenerator::new_from_yaml(yaml, locale)?),
+            )),
+            FieldKind::PhoneNumber => Ok((
+                FieldKind::PhoneNumber,
+                Box::new(PhoneNumberGenerator::new_from_yaml(yaml)?),
+            )),
+            FieldKind::Username => Ok((
+                FieldKind::Username,
+                Box::new(UsernameGenerator::new_from_yaml(yaml, locale)?),
+            )),
             FieldKind::Unknown(field) => Err(ConfigParseError {
                 field: s.to_string(),
                 kind: ConfigParseErrorKind::UnknownField(field),
@@ -109,13 +126,13 @@ pub fn new_from_yaml(
 mod tests {
     use crate::masker::{
         error::{ConfigParseError, ConfigParseErrorKind},
-        generator::new_from_yaml,
+        generator::{new_from_yaml, Locale},
     };
 
     #[test]
     fn get_generator_from_yaml() -> Result<(), ConfigParseError> {
         let yaml = serde_yaml::from_str("kind: LastName").unwrap();
-        _ = new_from_yaml(&yaml)?;
+        _ = new_from_yaml(&yaml, Locale::default())?;
         Ok(())
     }
 
@@ -123,7 +140,7 @@ mod tests {
     fn fail_on_unknown_kind() {
         let field = "SomethingElse";
         let yaml = serde_yaml::from_str(format!("kind: {field}").as_str()).unwrap();
-        let err = new_from_yaml(&yaml)
+        let err = new_from_yaml(&yaml, Locale::default())
             .err()
             .expect("expected to get error on parse, got generator instead");
         assert_eq!(
@@ -137,7 +154,7 @@ mod tests {
     #[test]
     fn fail_on_missing_kind() {
         let yaml = serde_yaml::from_str("some_other_key: SomethingElse").unwrap();
-        let err = new_from_yaml(&yaml)
+        let err = new_from_yaml(&yaml, Locale::default())
             .err()
             .expect("expected to get error on parse, got generator instead");
         assert_eq!(
@@ -1,5 +1,8 @@
+use rand::rngs::StdRng;
+
 use super::{GeneratedValue, Generator, GeneratorError, Options};
-type GeneratorFunction = fn(&Options) -> Result<GeneratedValue, GeneratorError>;
+type GeneratorFunction =
+    Box<dyn Fn(&Options, &mut StdRng) -> Result<GeneratedValue, GeneratorError> + Sync + Send>;
 pub struct SimpleGenerator {
     generator: GeneratorFunction,
 }
@@ -9,15 +12,25 @@ pub struct SimpleGenerator {
 // These functions are simple thus are just implemented as public functions and can be re-used. The
 // wrapper is used for the type consistency in a global masker object.
 impl SimpleGenerator {
-    pub fn new(generator_fn: GeneratorFunction) -> Self {
+    pub fn new<F>(generator_fn: F) -> Self
+    where
+        F: Fn(&Options, &mut StdRng) -> Result<GeneratedValue, GeneratorError>
+            + Sync
+            + Send
+            + 'static,
+    {
         Self {
-            generator: generator_fn,
+            generator: Box::new(generator_fn),
         }
     }
 }
 
 impl Generator for SimpleGenerator {
-    fn generate(&self, options: &Options) -> Result<GeneratedValue, super::GeneratorError> {
-        (self.generator)(options)
+    fn generate(
+        &self,
+        options: &Options,
+        rng: &mut StdRng,
+    ) -> Result<GeneratedValue, super::GeneratorError> {
+        (self.generator)(options, rng)
     }
 }
@@ -1,4 +1,7 @@
-use crate::masker::Entity;
+use std::collections::HashMap;
+
+use crate::database::shared;
+use crate::masker::{generator::Locale, Entity, SeedKey};
 
 use super::error::{ConfigParseError, ConfigParseErrorKind};
 
@@ -7,18 +10,27 @@ pub struct Masker {
 }
 
 impl Masker {
-    pub fn new(entities: Vec<Entity>) -> Self {
+    // Entities masking the same table are told apart by their order
+    pub fn new(mut entities: Vec<Entity>) -> Self {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for entity in entities.iter_mut() {
+            let ordinal = seen.entry(entity.get_table_name()).or_default();
+            *ordinal += 1;
+            entity.set_ordinal(*ordinal);
+        }
         Masker { entities }
     }
 
     pub fn new_from_yaml(yaml: &serde_yaml::Value) -> Result<Self, ConfigParseError> {
+        // Locale of all the fields that don't specify their own
+        let locale = Locale::new_from_yaml(yaml)?.unwrap_or_default();
         let mut schemas: Vec<Entity> = vec![];
         let field = "schemas";
         match yaml[field].as_sequence() {
             Some(seq) => seq
                 .iter()
                 .try_for_each(|schema_yaml| -> Result<(), ConfigParseError> {
-                    schemas.push(Entity::new_from_yaml(schema_yaml)?);
+                    schemas.push(Entity::new_from_yaml(schema_yaml, locale)?);
                     Ok(())
                 }),
             None => {
@@ -28,10 +40,172 @@ impl Masker {
                 })
             }
         }?;
-        Ok(Masker::new(schemas))
+
+        // Values of deterministic fields are keyed with the secret, which is better kept in env
+        let field = "secret";
+        let seed_key = match yaml.as_mapping().filter(|m| m.contains_key(field)) {
+            Some(m) => match shared::read_str_field(m, String::from(field))? {
+                s if s.is_empty() => {
+                    return Err(ConfigParseError {
+                        kind: ConfigParseErrorKind::UnexpectedFieldValue(s),
+                        field: String::from(field),
+                    })
+                }
+                s => Some(SeedKey::new(&s)),
+            },
+            None => None,
+        };
+        for entry in schemas
+            .iter_mut()
+            .flat_map(|s| s.get_entries_mut())
+            .filter(|f| f.is_deterministic())
+        {
+            match &seed_key {
+                Some(key) => entry.set_seed_key(key.clone()),
+                None => {
+                    return Err(ConfigParseError {
+                        kind: ConfigParseErrorKind::MissingField,
+                        field: String::from(field),
+                    })
+                }
+            }
+        }
+        let mut masker = Masker::new(schemas);
+        let field = "seed";
+        if let Some(m) = yaml.as_mapping().filter(|m| m.contains_key(field)) {
+            let seed = shared::read_str_or_int_field(m, String::from(field))?;
+            match seed.parse::<u64>() {
+                Ok(n) => masker.set_seed(n),
+                Err(_) => {
+                    return Err(ConfigParseError {
+                        kind: ConfigParseErrorKind::UnexpectedFieldValue(seed),
+                        field: String::from(field),
+                    })
+                }
+            }
+        }
+        Ok(masker)
+    }
+
+    // Makes the run reproducible: the same seed and the same data always give the same output
+    pub fn set_seed(&mut self, seed: u64) {
+        let seed_key = SeedKey::new(&seed.to_string());
+        for entity in self.entities.iter_mut() {
+            entity.set_seed_key(seed_key.clone());
+        }
     }
 
     pub fn get_entities(&self) -> &Vec<Entity> {
         &self.entities
     }
+
+    pub fn get_entities_mut(&mut self) -> &mut Vec<Entity> {
+        &mut self.entities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::Masker;
+    use crate::masker::error::{ConfigParseError, ConfigParseErrorKind};
+    use crate::masker::generator::GeneratedValue;
+
+    const SCHEMAS: &str = "schemas:
+  - table: customers
+    fields:
+      - name: contactFirstName
+        kind: FirstName
+        deterministic: true";
+
+    #[test]
+    fn deterministic_fields_are_keyed_with_the_secret() {
+        let yaml = serde_yaml::from_str(format!("secret: s3cr3t\n{SCHEMAS}").as_str()).unwrap();
+        let masker = Masker::new_from_yaml(&yaml).unwrap();
+        assert!(masker.get_entities()[0].get_entries()[0].is_deterministic());
+    }
+
+    #[test]
+    fn seed_makes_every_row_reproducible() {
+        let draw = |yaml: &str, key: &str| {
+            let yaml = serde_yaml::from_str(yaml).unwrap();
+            let masker = Masker::new_from_yaml(&yaml).unwrap();
+            masker.get_entities()[0]
+                .rng_for_row(&[GeneratedValue::Number(key.to_string())])
+                .gen::<u64>()
+        };
+        let seeded = format!("seed: 42\nsecret: s3cr3t\n{SCHEMAS}");
+        assert_eq!(draw(&seeded, "103"), draw(&seeded, "103"));
+        assert_ne!(draw(&seeded, "103"), draw(&seeded, "112"));
+
+        let yaml =
+            serde_yaml::from_str(format!("seed: -1\nsecret: s3cr3t\n{SCHEMAS}").as_str()).unwrap();
+        assert_eq!(
+            Masker::new_from_yaml(&yaml).err().unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from("-1")),
+                field: String::from("seed"),
+            }
+        );
+    }
+
+    #[test]
+    fn fails_on_deterministic_fields_without_secret() {
+        let yaml = serde_yaml::from_str(SCHEMAS).unwrap();
+        assert_eq!(
+            Masker::new_from_yaml(&yaml).err().unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::MissingField,
+                field: String::from("secret"),
+            }
+        );
+        let yaml = serde_yaml::from_str(format!("secret: ''\n{SCHEMAS}").as_str()).unwrap();
+        assert_eq!(
+            Masker::new_from_yaml(&yaml).err().unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(String::new()),
+                field: String::from("secret"),
+            }
+        );
+    }
+
+    #[test]
+    fn fields_are_generated_in_the_configured_locale() {
+        let yaml = serde_yaml::from_str(
+            "locale: ja_JP
+schemas:
+  - table: customers
+    fields:
+      - name: contactFirstName
+        kind: LastName
+      - name: contactLastName
+        kind: LastName
+        locale: en",
+        )
+        .unwrap();
+        let masker = Masker::new_from_yaml(&yaml).unwrap();
+        let fields = masker.get_entities()[0].get_entries();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut generate =
+            |i: usize| match fields[i].generate(&HashMap::new(), None, &mut rng, None) {
+                Ok(GeneratedValue::String(s)) => s,
+                other => panic!("unexpected value {other:?}"),
+            };
+        assert!(!generate(0).is_ascii());
+        assert!(generate(1).is_ascii());
+
+        let yaml = serde_yaml::from_str(format!("locale: de_DE\n{SCHEMAS}").as_str()).unwrap();
+        assert_eq!(
+            Masker::new_from_yaml(&yaml).err().unwrap(),
+            ConfigParseError {
+                kind: ConfigParseErrorKind::UnexpectedFieldValue(String::from(
+                    "de_DE (supported locales are en, fr_FR, ja_JP, zh_CN, zh_TW, ar_SA, pt_BR)"
+                )),
+                field: String::from("locale"),
+            }
+        );
+    }
 }
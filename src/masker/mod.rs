@@ -3,6 +3,8 @@ pub mod error;
 mod field;
 pub mod generator;
 mod main;
-pub use entity::{Entity, PkType};
-pub use field::{Field, FieldKind};
+mod seed;
+pub use entity::{Entity, PkColumn, PkType};
+pub use field::{Field, FieldColumn, FieldKind, OverflowPolicy};
 pub use main::Masker;
+pub use seed::SeedKey;
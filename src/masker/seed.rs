@@ -0,0 +1,44 @@
+use hmac::{Hmac, Mac};
+use rand::{rngs::StdRng, SeedableRng};
+use sha2::Sha256;
+
+// Keyed hash that the RNG of deterministic fields is seeded with. The same original value always
+// gets the same seed, and so is masked the same way in every table, run and DB masked with the
+// same secret, while the seed tells nothing about the value to anyone who doesn't know the secret.
+#[derive(Clone)]
+pub struct SeedKey {
+    mac: Hmac<Sha256>,
+}
+
+impl SeedKey {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size"),
+        }
+    }
+
+    pub fn rng_for(&self, value: &str) -> StdRng {
+        let mut mac = self.mac.clone();
+        mac.update(value.as_bytes());
+        StdRng::from_seed(mac.finalize().into_bytes().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::SeedKey;
+
+    #[test]
+    fn same_value_gets_the_same_rng() {
+        let key = SeedKey::new("secret");
+        let draw = |key: &SeedKey, value: &str| key.rng_for(value).gen::<u64>();
+        assert_eq!(draw(&key, "John Smith"), draw(&key, "John Smith"));
+        assert_ne!(draw(&key, "John Smith"), draw(&key, "Jane Smith"));
+        assert_ne!(
+            draw(&key, "John Smith"),
+            draw(&SeedKey::new("other secret"), "John Smith")
+        );
+    }
+}